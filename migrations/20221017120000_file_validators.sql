@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE files ADD COLUMN etag TEXT;
+ALTER TABLE files ADD COLUMN last_modified TEXT;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '7');
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS categories (
+    guild_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    discord_id TEXT NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '8');
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS hub_threads (
+    slack_channel_id TEXT NOT NULL PRIMARY KEY,
+    hub_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL
+);
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '9');
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS pending_files (
+    slack_channel_id TEXT NOT NULL,
+    slack_ts TEXT NOT NULL,
+    PRIMARY KEY (slack_channel_id, slack_ts)
+);
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '10');
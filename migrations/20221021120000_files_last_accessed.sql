@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE files ADD COLUMN last_accessed INTEGER NOT NULL DEFAULT 0;
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '11');
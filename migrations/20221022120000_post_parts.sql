@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS post_parts (
+    id TEXT NOT NULL PRIMARY KEY,
+    post_id TEXT NOT NULL,
+    discord_channel_id TEXT NOT NULL
+);
+
+INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', '12');
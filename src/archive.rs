@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use zip::ZipArchive;
+
+/// Why an export could not be loaded. `Read` and `NotAnExport` concern the whole archive, the
+/// others a single entry in it.
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("read archive :: {0:#}")]
+    Read(anyhow::Error),
+    #[error("not a Slack export, it has neither channels.json nor channel directories")]
+    NotAnExport,
+    #[error("parse {entry} :: {source}")]
+    Parse {
+        entry: String,
+        source: serde_json::Error,
+    },
+    #[error("{0} vanished from the archive")]
+    Vanished(String),
+    #[error("{0} not found in channels.json")]
+    UnknownChannel(String),
+}
+
+impl ArchiveError {
+    /// Whether only one entry is bad, rather than the archive as a whole.
+    pub fn is_entry_error(&self) -> bool {
+        !matches!(self, ArchiveError::Read(_) | ArchiveError::NotAnExport)
+    }
+}
+
+/// Files of a workspace export, either still zipped or unpacked into a directory.
+///
+/// Entries are named like zip entries, `<file>` at the top and `<dir>/<file>` for channel
+/// files.
+pub trait ExportArchive {
+    /// Every entry, in archive order.
+    fn entry_names(&mut self) -> Result<Vec<String>, anyhow::Error>;
+
+    /// Reader over the entry `name`, `None` if the export has no such entry.
+    fn open(&mut self, name: &str) -> Result<Option<Box<dyn Read + '_>>, anyhow::Error>;
+}
+
+impl<R: io::Read + io::Seek> ExportArchive for ZipArchive<R> {
+    fn entry_names(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        (0..self.len())
+            .map(|index| {
+                let entry = self
+                    .by_index(index)
+                    .with_context(|| format!("get zip entry at {}", index))?;
+                String::from_utf8(entry.name_raw().to_owned())
+                    .with_context(|| "read zip entry name as utf8".to_string())
+            })
+            .collect()
+    }
+
+    fn open(&mut self, name: &str) -> Result<Option<Box<dyn Read + '_>>, anyhow::Error> {
+        match self.by_name(name) {
+            Ok(entry) => Ok(Some(Box::new(entry))),
+            Err(zip::result::ZipError::FileNotFound) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("read {}", name)),
+        }
+    }
+}
+
+impl<A: ExportArchive + ?Sized> ExportArchive for Box<A> {
+    fn entry_names(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        (**self).entry_names()
+    }
+
+    fn open(&mut self, name: &str) -> Result<Option<Box<dyn Read + '_>>, anyhow::Error> {
+        (**self).open(name)
+    }
+}
+
+/// An export someone already unzipped.
+pub struct DirArchive {
+    root: PathBuf,
+}
+
+impl DirArchive {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+        }
+    }
+}
+
+impl ExportArchive for DirArchive {
+    fn entry_names(&mut self) -> Result<Vec<String>, anyhow::Error> {
+        let read_dir = |dir: &Path| -> Result<Vec<fs::DirEntry>, anyhow::Error> {
+            let mut entries = fs::read_dir(dir)
+                .with_context(|| format!("read {}", dir.display()))?
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("read {}", dir.display()))?;
+            entries.sort_by_key(|entry| entry.file_name());
+            Ok(entries)
+        };
+        let file_name = |entry: &fs::DirEntry| {
+            entry
+                .file_name()
+                .into_string()
+                .map_err(|name| anyhow::anyhow!("{:?} is not utf8", name))
+        };
+        let mut names = Vec::new();
+        for entry in read_dir(&self.root)? {
+            let name = file_name(&entry)?;
+            if !entry.file_type()?.is_dir() {
+                names.push(name);
+                continue;
+            }
+            for file in read_dir(&entry.path())? {
+                if file.file_type()?.is_file() {
+                    names.push(format!("{}/{}", name, file_name(&file)?));
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn open(&mut self, name: &str) -> Result<Option<Box<dyn Read + '_>>, anyhow::Error> {
+        let path = self.root.join(name);
+        match fs::File::open(&path) {
+            Ok(file) => Ok(Some(Box::new(io::BufReader::new(file)))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+        }
+    }
+}
+
+/// Open the export at `path`, a zip or a directory it was unzipped into.
+pub fn open(path: &Path) -> Result<Box<dyn ExportArchive + Send>, anyhow::Error> {
+    if path.is_dir() {
+        return Ok(Box::new(DirArchive::new(path)));
+    }
+    let archive = fs::File::open(path).with_context(|| "Reading msg archive")?;
+    let archive =
+        ZipArchive::new(io::BufReader::new(archive)).with_context(|| "Open msg archive")?;
+    Ok(Box::new(archive))
+}
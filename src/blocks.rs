@@ -0,0 +1,154 @@
+use serde_json::Value;
+
+/// Markdown of block kit `blocks`, empty when none of them carries text. Mentions stay in
+/// Slack's `<@U…>` form, to be replaced like in `text`, and kinds without text are skipped.
+pub fn render_blocks(blocks: &[Value]) -> String {
+    let mut out = Vec::new();
+    for block in blocks {
+        let rendered = match block["type"].as_str() {
+            Some("rich_text") => render_rich_text(&block["elements"]),
+            Some("section") => {
+                let mut lines = Vec::new();
+                lines.extend(text_object(&block["text"]));
+                for field in block["fields"].as_array().into_iter().flatten() {
+                    lines.extend(text_object(field));
+                }
+                lines.join("\n")
+            }
+            Some("header") => text_object(&block["text"])
+                .map(|text| format!("**{}**", text))
+                .unwrap_or_default(),
+            Some("context") => block["elements"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(text_object)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Some("divider") => "---".to_owned(),
+            _ => String::new(),
+        };
+        if !rendered.is_empty() {
+            out.push(rendered);
+        }
+    }
+    out.join("\n")
+}
+
+/// `text` of a `plain_text` or `mrkdwn` text object.
+fn text_object(object: &Value) -> Option<String> {
+    object["text"]
+        .as_str()
+        .filter(|text| !text.is_empty())
+        .map(str::to_owned)
+}
+
+fn render_rich_text(elements: &Value) -> String {
+    let mut out = Vec::new();
+    for element in elements.as_array().into_iter().flatten() {
+        let elements = &element["elements"];
+        match element["type"].as_str() {
+            Some("rich_text_section") => out.push(render_inline(elements)),
+            Some("rich_text_preformatted") => {
+                out.push(format!("```\n{}\n```", render_plain(elements)))
+            }
+            Some("rich_text_quote") => out.push(
+                render_inline(elements)
+                    .lines()
+                    .map(|line| format!("> {}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            Some("rich_text_list") => {
+                let ordered = element["style"].as_str() == Some("ordered");
+                let indent = "  ".repeat(element["indent"].as_u64().unwrap_or(0) as usize);
+                for (index, item) in elements.as_array().into_iter().flatten().enumerate() {
+                    let marker = if ordered {
+                        format!("{}.", index + 1)
+                    } else {
+                        "-".to_owned()
+                    };
+                    out.push(format!(
+                        "{}{} {}",
+                        indent,
+                        marker,
+                        render_inline(&item["elements"])
+                    ));
+                }
+            }
+            _ => (),
+        }
+    }
+    out.join("\n")
+}
+
+/// Inline elements of a section, styles turned into markdown.
+fn render_inline(elements: &Value) -> String {
+    let mut out = String::new();
+    for element in elements.as_array().into_iter().flatten() {
+        let piece = match element["type"].as_str() {
+            Some("text") => {
+                let text = element["text"].as_str().unwrap_or_default();
+                let style = &element["style"];
+                // markers around surrounding spaces would not render
+                let start = text.len() - text.trim_start().len();
+                let end = text.trim_end().len().max(start);
+                let mut inner = text[start..end].to_owned();
+                if !inner.is_empty() {
+                    for (key, marker) in [
+                        ("code", "`"),
+                        ("bold", "**"),
+                        ("italic", "*"),
+                        ("strike", "~~"),
+                    ] {
+                        if style[key].as_bool() == Some(true) {
+                            inner = format!("{}{}{}", marker, inner, marker);
+                        }
+                    }
+                }
+                format!("{}{}{}", &text[..start], inner, &text[end..])
+            }
+            Some("link") => {
+                let url = element["url"].as_str().unwrap_or_default();
+                match element["text"].as_str().filter(|text| !text.is_empty()) {
+                    Some(text) => format!("[{}](<{}>)", text, url),
+                    None => url.to_owned(),
+                }
+            }
+            Some("user") => format!("<@{}>", element["user_id"].as_str().unwrap_or_default()),
+            Some("usergroup") => {
+                format!("@{}", element["usergroup_id"].as_str().unwrap_or_default())
+            }
+            Some("channel") => format!("#{}", element["channel_id"].as_str().unwrap_or_default()),
+            Some("broadcast") => format!("<!{}>", element["range"].as_str().unwrap_or_default()),
+            Some("emoji") => {
+                let name = element["name"].as_str().unwrap_or_default();
+                match emojis::get_by_shortcode(name) {
+                    Some(emoji) => emoji.as_str().to_owned(),
+                    None => format!(":{}:", name),
+                }
+            }
+            _ => String::new(),
+        };
+        out.push_str(&piece);
+    }
+    out
+}
+
+/// Inline elements without styles, for code blocks.
+fn render_plain(elements: &Value) -> String {
+    elements
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|element| match element["type"].as_str() {
+            Some("link") => element["text"]
+                .as_str()
+                .filter(|text| !text.is_empty())
+                .or(element["url"].as_str())
+                .unwrap_or_default()
+                .to_owned(),
+            _ => element["text"].as_str().unwrap_or_default().to_owned(),
+        })
+        .collect()
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{slack, ChannelReport, Db, SlackChannel};
+
+/// Progress of one channel.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ChannelCheckpoint {
+    pub name: String,
+    /// Latest message recorded as posted.
+    pub last_ts: Option<slack::TimeStamp>,
+    /// Messages posted over every run.
+    pub posted: usize,
+    pub failed_files: usize,
+}
+
+/// Migration progress kept as JSON next to the database, readable without SQL. `posts`
+/// remains what resuming goes by, this only mirrors it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Checkpoint {
+    /// Keyed by Slack channel id.
+    pub channels: BTreeMap<String, ChannelCheckpoint>,
+    pub messages: usize,
+    pub failed_files: usize,
+}
+
+impl Checkpoint {
+    /// The checkpoint at `path`, empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match fs::read(path) {
+            Ok(src) => serde_json::from_slice(&src)
+                .with_context(|| format!("parse state file {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("read state file {}", path.display())),
+        }
+    }
+
+    /// Write through a temporary file renamed over `path`, so an interrupted run never leaves
+    /// half a checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("write state file {}", path.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("replace state file {}", path.display()))
+    }
+
+    /// Take the latest posted message of every channel from `db` where the checkpoint
+    /// disagrees, as when a run died between posting and saving.
+    pub async fn reconcile(&mut self, db: &Db) -> Result<(), anyhow::Error> {
+        for (id, channel) in &mut self.channels {
+            let last_ts = db.last_posted_ts(id).await?;
+            if last_ts != channel.last_ts {
+                warn!(
+                    "state file has {} at {:?}, the database at {:?}",
+                    channel.name,
+                    channel.last_ts.as_ref().map(ToString::to_string),
+                    last_ts.as_ref().map(ToString::to_string)
+                );
+                channel.last_ts = last_ts;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a channel `post_channel` finished with `report`.
+    pub async fn record(
+        &mut self,
+        db: &Db,
+        channel: &SlackChannel,
+        report: &ChannelReport,
+    ) -> Result<(), anyhow::Error> {
+        let entry = self.channels.entry(channel.id.clone()).or_default();
+        entry.name = channel.name.clone();
+        entry.last_ts = db.last_posted_ts(&channel.id).await?;
+        entry.posted += report.posted;
+        entry.failed_files += report.failed_files.len();
+        self.messages += report.posted;
+        self.failed_files += report.failed_files.len();
+        Ok(())
+    }
+}
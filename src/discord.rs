@@ -1,13 +1,20 @@
 use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use reqwest::{multipart, Client};
+use reqwest::{multipart, Client, RequestBuilder, StatusCode};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use sqlx::{Decode, Encode};
-use tracing::{info, trace};
+use tokio::time::{sleep, Instant};
+use tracing::{info, trace, warn};
 
-const DISCORD_ENDPOINT_COMMON: &str = "https://discord.com/api/v10";
+const DISCORD_API_BASE: &str = "https://discord.com/api";
+
+/// API version requests go to unless `DiscordOptions::api_version` says otherwise.
+pub const DISCORD_API_VERSION: &str = "v10";
 
 pub struct BotToken(String);
 pub struct GuildId(String);
@@ -21,6 +28,12 @@ impl From<String> for ChannelId {
     }
 }
 
+impl std::fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 impl<'r, DB: sqlx::Database> sqlx::Decode<'r, DB> for ChannelId
 where
     &'r str: Decode<'r, DB>,
@@ -63,6 +76,11 @@ pub enum SecretLoadError {
 }
 
 impl BotToken {
+    /// A token read from somewhere other than the environment, such as a file.
+    pub fn new(token: String) -> Self {
+        Self(token)
+    }
+
     pub fn from_env(env_name: &str) -> Result<Self, SecretLoadError> {
         match std::env::var(env_name) {
             Ok(var) => Ok(Self(var)),
@@ -77,6 +95,10 @@ impl BotToken {
 }
 
 impl GuildId {
+    pub fn new(id: String) -> Self {
+        Self(id)
+    }
+
     pub fn from_env(env_name: &str) -> Result<Self, SecretLoadError> {
         match std::env::var(env_name) {
             Ok(var) => Ok(Self(var)),
@@ -85,12 +107,17 @@ impl GuildId {
         }
     }
 
-    fn as_str(&self) -> &str {
+    pub(crate) fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// The `@everyone` role shares its id with the guild.
+    pub fn everyone_role(&self) -> String {
+        self.0.clone()
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ChannelGet {
     pub name: String,
     pub id: ChannelId,
@@ -98,15 +125,110 @@ pub struct ChannelGet {
     pub channel_type: ChannelType,
     pub parent_id: Option<ChannelId>,
     pub message_count: Option<u64>,
+    #[serde(default)]
+    pub permission_overwrites: Vec<PermissionOverwrite>,
+    #[serde(default)]
+    pub available_tags: Vec<ForumTag>,
+}
+
+/// Bit of the `View Channel` permission.
+pub const PERMISSION_VIEW_CHANNEL: u64 = 1 << 10;
+
+/// Bit of the `Administrator` permission, which implies every other.
+pub const PERMISSION_ADMINISTRATOR: u64 = 1 << 3;
+
+/// Permissions a migration uses, by the name the Discord client shows them under.
+pub const MIGRATION_PERMISSIONS: &[(&str, u64)] = &[
+    ("Manage Channels", 1 << 4),
+    ("View Channel", PERMISSION_VIEW_CHANNEL),
+    ("Send Messages", 1 << 11),
+    ("Manage Messages", 1 << 13),
+    ("Attach Files", 1 << 15),
+    ("Read Message History", 1 << 16),
+    ("Manage Threads", 1 << 34),
+    ("Create Public Threads", 1 << 35),
+    ("Send Messages in Threads", 1 << 38),
+];
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OverwriteType {
+    Role = 0,
+    Member = 1,
+}
+
+/// Permissions granted or taken from a role or member on one channel. Discord sends the
+/// bit sets as decimal strings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PermissionOverwrite {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub overwrite_type: OverwriteType,
+    pub allow: String,
+    pub deny: String,
+}
+
+impl PermissionOverwrite {
+    pub fn deny_view(role: String) -> Self {
+        Self {
+            id: role,
+            overwrite_type: OverwriteType::Role,
+            allow: "0".to_owned(),
+            deny: PERMISSION_VIEW_CHANNEL.to_string(),
+        }
+    }
+
+    /// Every permission of [`MIGRATION_PERMISSIONS`] for the member `user`.
+    pub fn allow_migration(user: String) -> Self {
+        Self {
+            id: user,
+            overwrite_type: OverwriteType::Member,
+            allow: MIGRATION_PERMISSIONS
+                .iter()
+                .fold(0, |bits, (_, bit)| bits | bit)
+                .to_string(),
+            deny: "0".to_owned(),
+        }
+    }
+
+    /// Overwrites hiding a channel from `@everyone` of `guild`. The bot `bot` is let back in,
+    /// as the role denial holds it out as well unless it is an administrator.
+    pub fn hide_from_everyone(guild: &GuildId, bot: &str) -> Vec<Self> {
+        vec![
+            Self::deny_view(guild.everyone_role()),
+            Self::allow_migration(bot.to_owned()),
+        ]
+    }
+
+    /// Whether this lets the member `user` view the channel.
+    pub fn lets_member_view(&self, user: &str) -> bool {
+        self.overwrite_type == OverwriteType::Member
+            && self.id == user
+            && self
+                .allow
+                .parse::<u64>()
+                .is_ok_and(|allow| allow & PERMISSION_VIEW_CHANNEL != 0)
+    }
+
+    /// Whether this hides the channel from the role `role`.
+    pub fn hides_from_role(&self, role: &str) -> bool {
+        self.overwrite_type == OverwriteType::Role
+            && self.id == role
+            && self
+                .deny
+                .parse::<u64>()
+                .is_ok_and(|deny| deny & PERMISSION_VIEW_CHANNEL != 0)
+    }
 }
 
-#[derive(Serialize_repr, Deserialize_repr, Debug, PartialEq, Eq)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ChannelType {
     GuildText = 0,
     GuildVoice = 2,
     GuildCategory = 4,
     PublicThread = 11,
+    GuildForum = 15,
 }
 
 #[derive(Serialize)]
@@ -115,6 +237,38 @@ pub struct ChannelPost {
     #[serde(rename = "type")]
     pub channel_type: ChannelType,
     pub parent_id: Option<ChannelId>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub permission_overwrites: Vec<PermissionOverwrite>,
+    /// Tags posts of a forum can be given.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub available_tags: Vec<ForumTagPost>,
+}
+
+#[derive(Serialize)]
+pub struct ForumTagPost {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ForumTag {
+    pub id: String,
+    pub name: String,
+}
+
+/// Channel flag of the forum post pinned to the top of its forum.
+pub const CHANNEL_FLAG_PINNED: u64 = 1 << 1;
+
+/// Discord applies at most this many tags to a forum post.
+pub const FORUM_POST_MAX_TAGS: usize = 5;
+
+/// Discord rejects thread names longer than this.
+pub const THREAD_NAME_MAX_CHARS: usize = 100;
+
+/// The thread a forum post was started as, with its first message.
+#[derive(Deserialize)]
+pub struct ForumThreadGet {
+    pub id: ChannelId,
+    pub message: Option<MessageGet>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -125,87 +279,471 @@ pub enum Error {
     Schema(serde_json::Error),
     #[error("invalid mime :: {0}")]
     InvalidMimeType(reqwest::Error),
+    #[error("bundle attachments :: {0}")]
+    Bundle(zip::result::ZipError),
+    #[error("rate limited, gave up after {0} retries")]
+    RateLimited(u32),
+    #[error("status {status} :: {body}")]
+    Status { status: u16, body: String },
 }
 
-async fn get_method<T: DeserializeOwned>(token: &BotToken, url: &str) -> Result<T, Error> {
-    let response = Client::new()
-        .get(url)
-        .header("Authorization", format!("Bot {}", token.as_str()))
-        .send()
-        .await
-        .map_err(Error::Request)?
-        .text()
-        .await
-        .map_err(Error::Request)?;
-    trace!("response: {}", response);
-    serde_json::from_str(&response).map_err(Error::Schema)
-}
-
-async fn post_method_json<R: DeserializeOwned, P: Serialize>(
-    token: &BotToken,
-    url: &str,
-    payload: P,
-) -> Result<R, Error> {
-    let response = Client::new()
-        .post(url)
-        .header("Authorization", format!("Bot {}", token.as_str()))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(Error::Request)?
-        .text()
-        .await
-        .map_err(Error::Request)?;
-    trace!("response: {}", response);
-    serde_json::from_str(&response).map_err(Error::Schema)
-}
-
-async fn patch_method_json<R: DeserializeOwned, P: Serialize>(
-    token: &BotToken,
-    url: &str,
-    payload: P,
-) -> Result<R, Error> {
-    let response = Client::new()
-        .patch(url)
-        .header("Authorization", format!("Bot {}", token.as_str()))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(Error::Request)?
-        .text()
-        .await
-        .map_err(Error::Request)?;
-    trace!("response: {}", response);
-    serde_json::from_str(&response).map_err(Error::Schema)
+/// JSON error code of a guild at its 500 channel limit.
+pub const ERROR_MAX_GUILD_CHANNELS: u64 = 30013;
+
+/// JSON error code of a request body failing validation, detailed per field in `errors`.
+pub const ERROR_INVALID_FORM_BODY: u64 = 50035;
+
+impl Error {
+    /// Discord's JSON error code of a rejected request.
+    pub fn json_code(&self) -> Option<u64> {
+        match self {
+            Error::Status { body, .. } => {
+                serde_json::from_str::<serde_json::Value>(body).ok()?["code"].as_u64()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Status { status: 404, .. })
+    }
+
+    /// Whether Discord rejected the `name` of a channel, reported as a form error
+    /// (`ERROR_INVALID_FORM_BODY`) on that field.
+    pub fn is_invalid_name(&self) -> bool {
+        match self {
+            Error::Status { status: 400, body } => serde_json::from_str::<serde_json::Value>(body)
+                .is_ok_and(|body| {
+                    body["code"].as_u64() == Some(ERROR_INVALID_FORM_BODY)
+                        && !body["errors"]["name"].is_null()
+                }),
+            _ => false,
+        }
+    }
+
+    /// Whether Discord rejected a channel for the 50 channel limit of its category, reported
+    /// as a form error on `parent_id`.
+    pub fn is_category_full(&self) -> bool {
+        matches!(self, Error::Status { body, .. } if body.contains("CHANNEL_PARENT_MAX_CHANNELS"))
+    }
 }
 
-pub async fn get_channels(guild: &GuildId, token: &BotToken) -> Result<Vec<ChannelGet>, Error> {
-    get_method(
-        token,
-        &format!(
-            "{}/guilds/{}/channels",
-            DISCORD_ENDPOINT_COMMON,
-            guild.as_str()
-        ),
-    )
-    .await
-}
-
-pub async fn post_channel(
-    guild: &GuildId,
-    token: &BotToken,
-    channel: &ChannelPost,
-) -> Result<ChannelGet, Error> {
-    post_method_json(
-        token,
-        &format!(
+/// Token bucket shared by every request of a [`DiscordClient`], keeping the bot under
+/// Discord's global rate limit.
+pub struct RateGovernor {
+    requests_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Set by a global 429, until then nothing is sent.
+    paused_until: Option<Instant>,
+}
+
+impl RateGovernor {
+    pub fn new(requests_per_second: u32) -> Self {
+        let requests_per_second = f64::from(requests_per_second.max(1));
+        Self {
+            requests_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: requests_per_second,
+                updated: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        bucket.paused_until = None;
+                        let refill =
+                            (now - bucket.updated).as_secs_f64() * self.requests_per_second;
+                        bucket.tokens = (bucket.tokens + refill).min(self.requests_per_second);
+                        bucket.updated = now;
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= 1.0;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - bucket.tokens) / self.requests_per_second)
+                    }
+                }
+            };
+            trace!("global rate limit, wait {:?}", wait);
+            sleep(wait).await;
+        }
+    }
+
+    /// Hold every request back for `duration`, as Discord asks on a global 429.
+    pub fn pause(&self, duration: Duration) {
+        let mut bucket = self.bucket.lock().unwrap();
+        let until = Instant::now() + duration;
+        if bucket
+            .paused_until
+            .is_none_or(|paused_until| paused_until < until)
+        {
+            bucket.paused_until = Some(until);
+        }
+    }
+}
+
+/// Per-route limits Discord reports in `X-RateLimit-*` headers. Routes sharing a bucket hash
+/// and major parameter (channel, guild or webhook) wait on each other, others do not, so
+/// posting to one channel is never held back by another channel's bucket.
+#[derive(Default)]
+pub struct RouteLimits {
+    inner: Mutex<RouteLimitsInner>,
+}
+
+#[derive(Default)]
+struct RouteLimitsInner {
+    /// Bucket hash of every route seen so far.
+    buckets: HashMap<String, String>,
+    /// Keyed by bucket hash and major parameter.
+    limits: HashMap<String, RouteLimit>,
+}
+
+struct RouteLimit {
+    remaining: u64,
+    reset_at: Instant,
+}
+
+impl RouteLimits {
+    /// Wait until the bucket of `route` has a request left and take it. Routes whose bucket is
+    /// not known yet go right away.
+    async fn acquire(&self, route: &Route) {
+        loop {
+            let wait = {
+                let mut inner = self.inner.lock().unwrap();
+                let Some(key) = inner
+                    .buckets
+                    .get(&route.path)
+                    .map(|bucket| route.limit_key(bucket))
+                else {
+                    return;
+                };
+                let Some(limit) = inner.limits.get_mut(&key) else {
+                    return;
+                };
+                let now = Instant::now();
+                if limit.remaining > 0 || limit.reset_at <= now {
+                    limit.remaining = limit.remaining.saturating_sub(1);
+                    return;
+                }
+                limit.reset_at - now
+            };
+            trace!("route {} rate limit, wait {:?}", route.path, wait);
+            sleep(wait).await;
+        }
+    }
+
+    /// Record the limit headers of a response to `route`.
+    fn update(&self, route: &Route, headers: &reqwest::header::HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let (Some(bucket), Some(remaining), Some(reset_after)) = (
+            header("x-ratelimit-bucket"),
+            header("x-ratelimit-remaining").and_then(|value| value.parse().ok()),
+            header("x-ratelimit-reset-after").and_then(|value| value.parse::<f64>().ok()),
+        ) else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        inner.buckets.insert(route.path.clone(), bucket.to_owned());
+        inner.limits.insert(
+            route.limit_key(bucket),
+            RouteLimit {
+                remaining,
+                reset_at: Instant::now() + Duration::from_secs_f64(reset_after.max(0.0)),
+            },
+        );
+    }
+}
+
+/// A request's route, `POST /channels/{id}/messages`, with its major parameter apart.
+struct Route {
+    /// Method and path with every id but the major parameter replaced.
+    path: String,
+    major: String,
+}
+
+impl Route {
+    fn new(method: &reqwest::Method, url: &reqwest::Url) -> Self {
+        let mut path = method.to_string();
+        let mut major = String::new();
+        let mut previous = "";
+        for segment in url.path_segments().into_iter().flatten() {
+            path.push('/');
+            if segment.bytes().all(|byte| byte.is_ascii_digit()) {
+                if major.is_empty() && matches!(previous, "channels" | "guilds" | "webhooks") {
+                    major = segment.to_owned();
+                    path.push_str(segment);
+                } else {
+                    path.push_str(":id");
+                }
+            } else {
+                path.push_str(segment);
+            }
+            previous = segment;
+        }
+        Self { path, major }
+    }
+
+    fn limit_key(&self, bucket: &str) -> String {
+        format!("{}:{}", bucket, self.major)
+    }
+}
+
+/// Requests per second Discord allows a bot globally.
+pub const DISCORD_GLOBAL_RATE_LIMIT: u32 = 50;
+
+pub struct DiscordOptions {
+    /// Requests per second across every call of the client.
+    pub global_rate_limit: u32,
+    /// Voluntary cap below `global_rate_limit`, for a token shared with other tools. `None` or
+    /// 0 leaves only the global limit.
+    pub rate_limit_rps: Option<u32>,
+    /// Attachments beyond this count are bundled into a single zip attachment.
+    pub max_attachment_count: Option<usize>,
+    /// How many times a request answered with 429 is sent again.
+    pub max_retries: u32,
+    /// Files of these mime types are uploaded as `text/plain` with a `.txt` suffix.
+    pub denied_mime_types: Vec<String>,
+    /// Version segment of the API URL, like `v10`.
+    pub api_version: String,
+}
+
+/// Mime types Discord may block or scan as executables and scripts.
+pub const DEFAULT_DENIED_MIME_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "application/javascript",
+    "text/javascript",
+    "application/x-sh",
+    "application/x-msdownload",
+    "application/x-msdos-program",
+    "application/vnd.microsoft.portable-executable",
+];
+
+impl Default for DiscordOptions {
+    fn default() -> Self {
+        Self {
+            global_rate_limit: DISCORD_GLOBAL_RATE_LIMIT,
+            rate_limit_rps: None,
+            max_attachment_count: None,
+            max_retries: 5,
+            denied_mime_types: DEFAULT_DENIED_MIME_TYPES
+                .iter()
+                .map(|mime| mime.to_string())
+                .collect(),
+            api_version: DISCORD_API_VERSION.to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RateLimitedGet {
+    retry_after: f64,
+    /// The whole bot is limited, not only the route.
+    #[serde(default)]
+    global: bool,
+}
+
+pub struct DiscordClient {
+    token: BotToken,
+    endpoint: String,
+    http: Client,
+    governor: RateGovernor,
+    routes: RouteLimits,
+    options: DiscordOptions,
+}
+
+impl DiscordClient {
+    pub fn new(token: BotToken, options: DiscordOptions) -> Self {
+        Self {
+            token,
+            endpoint: format!("{}/{}", DISCORD_API_BASE, options.api_version),
+            http: Client::new(),
+            governor: RateGovernor::new(match options.rate_limit_rps {
+                Some(cap) if cap > 0 => cap.min(options.global_rate_limit),
+                _ => options.global_rate_limit,
+            }),
+            routes: RouteLimits::default(),
+            options,
+        }
+    }
+
+    /// Send the request built by `build` once its route's bucket and the global governor allow,
+    /// waiting and sending it again while Discord answers 429. The wait is Discord's
+    /// `retry_after`, doubled on every further retry.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        build: impl Fn() -> Result<RequestBuilder, Error>,
+    ) -> Result<T, Error> {
+        let mut retries = 0;
+        loop {
+            let request = build()?
+                .header("Authorization", format!("Bot {}", self.token.as_str()))
+                .build()
+                .map_err(Error::Request)?;
+            let route = Route::new(request.method(), request.url());
+            self.routes.acquire(&route).await;
+            self.governor.acquire().await;
+            let response = self.http.execute(request).await.map_err(Error::Request)?;
+            self.routes.update(&route, response.headers());
+            let status = response.status();
+            let response = response.text().await.map_err(Error::Request)?;
+            trace!("response: {}", response);
+            match status {
+                StatusCode::TOO_MANY_REQUESTS => (),
+                status if status.is_client_error() || status.is_server_error() => {
+                    return Err(Error::Status {
+                        status: status.as_u16(),
+                        body: response,
+                    });
+                }
+                _ => {
+                    // 204 No Content carries no body
+                    let response = if response.is_empty() {
+                        "null"
+                    } else {
+                        &response
+                    };
+                    return serde_json::from_str(response).map_err(Error::Schema);
+                }
+            }
+            if retries >= self.options.max_retries {
+                return Err(Error::RateLimited(retries));
+            }
+            let (retry_after, global) = serde_json::from_str::<RateLimitedGet>(&response)
+                .map(|limited| (limited.retry_after, limited.global))
+                .unwrap_or((1.0, false));
+            let wait = Duration::from_secs_f64(retry_after.max(0.0) * 2f64.powi(retries as i32));
+            if global {
+                // concurrent requests, like other thread workers, have to hold back as well
+                self.governor.pause(wait);
+            }
+            warn!("rate limited, retry in {:?}", wait);
+            sleep(wait).await;
+            retries += 1;
+        }
+    }
+
+    async fn get_method<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        self.send(|| Ok(self.http.get(url))).await
+    }
+
+    async fn post_method_json<R: DeserializeOwned, P: Serialize>(
+        &self,
+        url: &str,
+        payload: P,
+    ) -> Result<R, Error> {
+        self.send(|| Ok(self.http.post(url).json(&payload))).await
+    }
+
+    async fn patch_method_json<R: DeserializeOwned, P: Serialize>(
+        &self,
+        url: &str,
+        payload: P,
+    ) -> Result<R, Error> {
+        self.send(|| Ok(self.http.patch(url).json(&payload))).await
+    }
+
+    pub async fn get_guild(&self, guild: &GuildId) -> Result<GuildInfo, Error> {
+        self.get_method(&format!("{}/guilds/{}", self.endpoint, guild.as_str()))
+            .await
+    }
+
+    /// The user the token belongs to.
+    pub async fn get_current_user(&self) -> Result<UserGet, Error> {
+        self.get_method(&format!("{}/users/@me", self.endpoint))
+            .await
+    }
+
+    /// Guild-wide permissions of `user`, from `@everyone` and its roles, before channel
+    /// overwrites. Owners and administrators get every bit.
+    pub async fn guild_permissions(&self, guild: &GuildId, user: &str) -> Result<u64, Error> {
+        let info = self.get_guild(guild).await?;
+        if info.owner_id == user {
+            return Ok(u64::MAX);
+        }
+        let member: MemberGet = self
+            .get_method(&format!(
+                "{}/guilds/{}/members/{}",
+                self.endpoint,
+                guild.as_str(),
+                user
+            ))
+            .await?;
+        let roles: Vec<RoleGet> = self
+            .get_method(&format!(
+                "{}/guilds/{}/roles",
+                self.endpoint,
+                guild.as_str()
+            ))
+            .await?;
+        let permissions = roles
+            .iter()
+            .filter(|role| role.id == guild.everyone_role() || member.roles.contains(&role.id))
+            .map(|role| role.permissions.parse::<u64>().unwrap_or(0))
+            .fold(0, |all, permissions| all | permissions);
+        if permissions & PERMISSION_ADMINISTRATOR != 0 {
+            return Ok(u64::MAX);
+        }
+        Ok(permissions)
+    }
+
+    pub async fn get_channels(&self, guild: &GuildId) -> Result<Vec<ChannelGet>, Error> {
+        self.get_method(&format!(
             "{}/guilds/{}/channels",
-            DISCORD_ENDPOINT_COMMON,
+            self.endpoint,
             guild.as_str()
-        ),
-        channel,
-    )
-    .await
+        ))
+        .await
+    }
+
+    pub async fn post_channel(
+        &self,
+        guild: &GuildId,
+        channel: &ChannelPost,
+    ) -> Result<ChannelGet, Error> {
+        self.post_method_json(
+            &format!("{}/guilds/{}/channels", self.endpoint, guild.as_str()),
+            channel,
+        )
+        .await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GuildInfo {
+    pub id: String,
+    pub name: String,
+    pub owner_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserGet {
+    pub id: String,
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MemberGet {
+    roles: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RoleGet {
+    id: String,
+    /// Bit set as a decimal string.
+    permissions: String,
 }
 
 #[derive(Debug, Clone)]
@@ -215,14 +753,32 @@ pub struct FilePost {
     pub body: Vec<u8>,
 }
 
+/// `flags` bit keeping Discord from unfurling links of a message into embeds.
+pub const MESSAGE_FLAG_SUPPRESS_EMBEDS: u64 = 1 << 2;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MessagePost {
     pub content: String,
+    /// Message flags such as [`MESSAGE_FLAG_SUPPRESS_EMBEDS`].
+    #[serde(default)]
+    pub flags: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct MessageId(String);
 
+impl From<String> for MessageId {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 impl<'r, DB: sqlx::Database> sqlx::Decode<'r, DB> for MessageId
 where
     &'r str: Decode<'r, DB>,
@@ -260,120 +816,483 @@ where
 pub struct MessageGet {
     pub id: MessageId,
     pub channel_id: ChannelId,
+    /// ISO 8601 time Discord recorded the message at.
+    #[serde(default)]
+    pub timestamp: Option<String>,
 }
 
-pub async fn post_message(
-    token: &BotToken,
-    channel: &ChannelId,
-    message: &MessagePost,
-    attached_files: HashMap<String, FilePost>,
-) -> Result<MessageGet, Error> {
-    if attached_files.is_empty() {
-        post_method_json(
-            token,
+/// Discord rejects attachment descriptions longer than this.
+const ATTACHMENT_DESCRIPTION_MAX_CHARS: usize = 1024;
+
+fn attachment_json(index: usize, filename: &str, file: &FilePost) -> serde_json::Value {
+    let mut attachment = json!({
+        "id": index,
+        "filename": filename,
+    });
+    if !file.title.is_empty() {
+        attachment["description"] = file
+            .title
+            .chars()
+            .take(ATTACHMENT_DESCRIPTION_MAX_CHARS)
+            .collect::<String>()
+            .into();
+    }
+    attachment
+}
+
+/// Rename files of a denied mime type to `<name>.txt` and upload them as plain text.
+fn defuse_denied(
+    attached_files: Vec<(String, FilePost)>,
+    denied: &[String],
+) -> Vec<(String, FilePost)> {
+    attached_files
+        .into_iter()
+        .map(|(filename, file)| {
+            let essence = file.mime.split(';').next().unwrap_or_default().trim();
+            if denied.iter().any(|mime| mime.eq_ignore_ascii_case(essence)) {
+                info!("upload {} ({}) as text", filename, file.mime);
+                (
+                    format!("{}.txt", filename),
+                    FilePost {
+                        mime: "text/plain".to_owned(),
+                        ..file
+                    },
+                )
+            } else {
+                (filename, file)
+            }
+        })
+        .collect()
+}
+
+/// Keep `max - 1` files as they are and zip the rest into one extra attachment.
+fn bundle_overflow(
+    attached_files: Vec<(String, FilePost)>,
+    max: usize,
+) -> Result<Vec<(String, FilePost)>, zip::result::ZipError> {
+    let mut kept = Vec::new();
+    let mut overflow = Vec::new();
+    for (filename, file) in attached_files {
+        if kept.len() + 1 < max {
+            kept.push((filename, file));
+        } else {
+            overflow.push((filename, file));
+        }
+    }
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (filename, file) in &overflow {
+        zip.start_file(filename, zip::write::FileOptions::default())?;
+        zip.write_all(&file.body)?;
+    }
+    let body = zip.finish()?.into_inner();
+    info!("bundle {} attachments into a zip", overflow.len());
+    kept.push((
+        "attachments.zip".to_owned(),
+        FilePost {
+            mime: "application/zip".to_owned(),
+            title: overflow
+                .iter()
+                .map(|(filename, _)| filename.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            body,
+        },
+    ));
+    Ok(kept)
+}
+
+impl DiscordClient {
+    pub async fn post_message(
+        &self,
+        channel: &ChannelId,
+        message: &MessagePost,
+        attached_files: Vec<(String, FilePost)>,
+    ) -> Result<MessageGet, Error> {
+        let url = format!("{}/channels/{}/messages", self.endpoint, channel.0);
+        self.post_message_payload(&url, message, attached_files, |message| message)
+            .await
+    }
+
+    /// Start a post of the forum `channel` named `name`, with `message` as its first message
+    /// and the tags of ids `tags` applied.
+    pub async fn post_forum_thread(
+        &self,
+        channel: &ChannelId,
+        name: &str,
+        tags: &[String],
+        message: &MessagePost,
+        attached_files: Vec<(String, FilePost)>,
+    ) -> Result<ForumThreadGet, Error> {
+        let url = format!("{}/channels/{}/threads", self.endpoint, channel.0);
+        self.post_message_payload(&url, message, attached_files, |message| {
+            json!({
+                "name": name,
+                "applied_tags": tags,
+                "message": message,
+            })
+        })
+        .await
+    }
+
+    /// Post `message` with `attached_files` to `url`, the message object placed in the payload
+    /// by `wrap`.
+    async fn post_message_payload<R: DeserializeOwned>(
+        &self,
+        url: &str,
+        message: &MessagePost,
+        attached_files: Vec<(String, FilePost)>,
+        wrap: impl Fn(serde_json::Value) -> serde_json::Value,
+    ) -> Result<R, Error> {
+        let attached_files = defuse_denied(attached_files, &self.options.denied_mime_types);
+        let attached_files = match self.options.max_attachment_count {
+            Some(max) if attached_files.len() > max => {
+                bundle_overflow(attached_files, max.max(1)).map_err(Error::Bundle)?
+            }
+            _ => attached_files,
+        };
+        if attached_files.is_empty() {
+            self.post_method_json(
+                url,
+                wrap(json!({
+                    "content": message.content,
+                    "flags": message.flags,
+                    // migrated messages never ping anyone, whatever `@` text they contain
+                    "allowed_mentions": { "parse": [] },
+                })),
+            )
+            .await
+        } else {
+            let attachments = attached_files
+                .iter()
+                .enumerate()
+                .map(|(index, (filename, file))| attachment_json(index, filename, file))
+                .collect::<Vec<_>>();
+            let payload_json = serde_json::to_string(&wrap(json!({
+                "content": message.content,
+                "flags": message.flags,
+                "allowed_mentions": { "parse": [] },
+                "attachments": attachments,
+            })))
+            .unwrap();
+            info!("post files");
+            // multipart bodies cannot be cloned, so every attempt builds the form again
+            self.send(|| {
+                let form = attached_files.iter().enumerate().try_fold(
+                    multipart::Form::new(),
+                    |form, (index, (filename, file))| {
+                        let part = multipart::Part::bytes(file.body.clone())
+                            .file_name(filename.clone())
+                            .mime_str(&file.mime)
+                            .map_err(Error::InvalidMimeType)?;
+                        Ok(form.part(format!("files[{}]", index), part))
+                    },
+                )?;
+                let form = form.part(
+                    "payload_json",
+                    multipart::Part::text(payload_json.clone())
+                        .mime_str("application/json")
+                        .unwrap(),
+                );
+                Ok(self.http.post(url).multipart(form))
+            })
+            .await
+        }
+    }
+
+    pub async fn get_channel(&self, channel: &ChannelId) -> Result<ChannelGet, Error> {
+        self.get_method(&format!("{}/channels/{}", self.endpoint, channel.0))
+            .await
+    }
+
+    pub async fn start_thread(
+        &self,
+        channel: &ChannelId,
+        message_id: &MessageId,
+        name: &str,
+    ) -> Result<ChannelGet, Error> {
+        self.post_method_json(
             &format!(
-                "{}/channels/{}/messages",
-                DISCORD_ENDPOINT_COMMON, channel.0
+                "{}/channels/{}/messages/{}/threads",
+                self.endpoint, channel.0, message_id.0
             ),
-            message,
+            json!({
+                "name": name,
+            }),
         )
         .await
-    } else {
-        let attachments = attached_files
+    }
+
+    pub async fn pin_message(
+        &self,
+        channel: &ChannelId,
+        message_id: &MessageId,
+    ) -> Result<(), Error> {
+        let url = format!(
+            "{}/channels/{}/pins/{}",
+            self.endpoint, channel.0, message_id.0
+        );
+        self.send(|| Ok(self.http.put(&url))).await
+    }
+
+    /// Pin the forum post `thread` to the top of its forum.
+    pub async fn pin_forum_post(&self, thread: &ChannelId) -> Result<ChannelGet, Error> {
+        self.patch_method_json(
+            &format!("{}/channels/{}", self.endpoint, thread.0),
+            &json!({ "flags": CHANNEL_FLAG_PINNED }),
+        )
+        .await
+    }
+
+    pub async fn archive_channel(&self, channel: &ChannelId) -> Result<ChannelGet, Error> {
+        self.patch_method_json(
+            &format!("{}/channels/{}", self.endpoint, channel.0),
+            &json!({"archived": true}),
+        )
+        .await
+    }
+
+    pub async fn delete_message(
+        &self,
+        channel: &ChannelId,
+        message_id: &MessageId,
+    ) -> Result<(), Error> {
+        let url = format!(
+            "{}/channels/{}/messages/{}",
+            self.endpoint, channel.0, message_id.0
+        );
+        self.send(|| Ok(self.http.delete(&url))).await
+    }
+
+    /// Delete 2 to [`BULK_DELETE_MAX`] messages younger than [`BULK_DELETE_MAX_AGE`] at once.
+    pub async fn bulk_delete_messages(
+        &self,
+        channel: &ChannelId,
+        message_ids: &[MessageId],
+    ) -> Result<(), Error> {
+        self.post_method_json(
+            &format!(
+                "{}/channels/{}/messages/bulk-delete",
+                self.endpoint, channel.0
+            ),
+            json!({ "messages": message_ids }),
+        )
+        .await
+    }
+
+    /// Move each channel of `positions` to its position in the channel list. Channels left
+    /// out keep theirs, except for Discord shifting them around the moved ones.
+    pub async fn set_channel_positions(
+        &self,
+        guild: &GuildId,
+        positions: &[(&ChannelId, u32)],
+    ) -> Result<(), Error> {
+        let url = format!("{}/guilds/{}/channels", self.endpoint, guild.as_str());
+        let payload = positions
             .iter()
-            .enumerate()
-            .map(|(index, (filename, file))| {
-                json!({
-                    "id": index,
-                    "filename": filename,
-                    "description": file.title.clone(),
-                })
-            })
+            .map(|(id, position)| json!({"id": id, "position": position}))
             .collect::<Vec<_>>();
-        let parts = attached_files
-            .into_iter()
-            .map(|(filename, file)| {
-                multipart::Part::bytes(file.body)
-                    .file_name(filename)
-                    .mime_str(&file.mime)
-                    .map_err(Error::InvalidMimeType)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-        let form = parts
-            .into_iter()
-            .enumerate()
-            .fold(multipart::Form::new(), |form, (index, part)| {
-                form.part(format!("files[{}]", index), part)
-            });
-        let payload_json = json!({
-            "content": message.content,
-            "attachments": attachments,
+        self.send::<serde::de::IgnoredAny>(|| Ok(self.http.patch(&url).json(&payload)))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a channel or thread, with every message in it.
+    pub async fn delete_channel(&self, channel: &ChannelId) -> Result<(), Error> {
+        let url = format!("{}/channels/{}", self.endpoint, channel.0);
+        self.send::<serde::de::IgnoredAny>(|| Ok(self.http.delete(&url)))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Most messages one bulk delete takes.
+pub const BULK_DELETE_MAX: usize = 100;
+
+/// Messages older than this can only be deleted one by one.
+pub const BULK_DELETE_MAX_AGE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// Milliseconds since the Unix epoch of the first second of 2015, where snowflakes count from.
+const DISCORD_EPOCH_MS: u64 = 1_420_070_400_000;
+
+impl MessageId {
+    /// When the message was created, from its snowflake. `None` for ids that are not
+    /// snowflakes, like the ones of the Markdown sink.
+    pub fn created_at(&self) -> Option<std::time::SystemTime> {
+        let snowflake = self.0.parse::<u64>().ok()?;
+        Some(std::time::UNIX_EPOCH + Duration::from_millis((snowflake >> 22) + DISCORD_EPOCH_MS))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serve `responses`, one connection each, at the returned address. The server yields
+    /// the request lines it received.
+    pub(crate) async fn serve_http(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // headers, then as much body as they announce
+                let body_start = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..body_start]).to_string();
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                while request.len() < body_start + length {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                requests.push(head.lines().next().unwrap_or_default().to_owned());
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
         });
-        let form = form.part(
-            "payload_json",
-            multipart::Part::text(serde_json::to_string(&payload_json).unwrap())
-                .mime_str("application/json")
-                .unwrap(),
-        );
-        info!("post files");
-        let response = reqwest::Client::new()
-            .post(format!(
-                "{}/channels/{}/messages",
-                DISCORD_ENDPOINT_COMMON, channel.0
-            ))
-            .multipart(form)
-            .header("Authorization", format!("Bot {}", token.0))
-            .send()
+        (endpoint, server)
+    }
+
+    /// [`serve_http`] to a client of the returned one.
+    pub(crate) async fn serve(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (DiscordClient, tokio::task::JoinHandle<Vec<String>>) {
+        let (endpoint, server) = serve_http(responses).await;
+        let client = DiscordClient {
+            endpoint,
+            ..DiscordClient::new(
+                BotToken::new("token".to_owned()),
+                DiscordOptions {
+                    max_retries: 2,
+                    ..Default::default()
+                },
+            )
+        };
+        (client, server)
+    }
+
+    const RATE_LIMITED: &str =
+        r#"{"message": "You are being rate limited.", "retry_after": 0.0, "global": false}"#;
+
+    const THREAD: &str = r#"{"id": "T1", "name": "slack thread", "type": 11, "parent_id": "C1"}"#;
+
+    #[tokio::test]
+    async fn rate_limited_thread_starts_are_retried() {
+        let (client, server) = serve(vec![(429, RATE_LIMITED), (200, THREAD)]).await;
+        let thread = client
+            .start_thread(
+                &"C1".to_owned().into(),
+                &"M1".to_owned().into(),
+                "slack thread",
+            )
             .await
-            .map_err(Error::Request)?
-            .text()
+            .unwrap();
+        assert_eq!(thread.id.to_string(), "T1");
+        assert_eq!(
+            server.await.unwrap(),
+            ["POST /channels/C1/messages/M1/threads HTTP/1.1"; 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_starts_give_up_after_max_retries() {
+        let (client, server) = serve(vec![(429, RATE_LIMITED); 3]).await;
+        let result = client
+            .start_thread(
+                &"C1".to_owned().into(),
+                &"M1".to_owned().into(),
+                "slack thread",
+            )
+            .await;
+        assert!(matches!(result, Err(Error::RateLimited(2))));
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    fn titled(title: &str) -> FilePost {
+        FilePost {
+            mime: "image/png".to_owned(),
+            title: title.to_owned(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn attachments_without_a_title_have_no_description() {
+        assert_eq!(
+            attachment_json(0, "shot.png", &titled("")),
+            json!({ "id": 0, "filename": "shot.png" })
+        );
+    }
+
+    #[test]
+    fn long_attachment_titles_are_truncated() {
+        let title = "é".repeat(ATTACHMENT_DESCRIPTION_MAX_CHARS + 10);
+        assert_eq!(
+            attachment_json(1, "shot.png", &titled(&title)),
+            json!({
+                "id": 1,
+                "filename": "shot.png",
+                "description": "é".repeat(ATTACHMENT_DESCRIPTION_MAX_CHARS),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn throttled_channel_creates_are_retried() {
+        const THROTTLED: &str =
+            r#"{"message": "You are being rate limited.", "retry_after": 0.0, "global": true}"#;
+        const CREATED: &str = r#"{"id": "C1", "name": "general", "type": 0, "parent_id": null}"#;
+        let (client, server) = serve(vec![(429, THROTTLED), (201, CREATED)]).await;
+        let channel = client
+            .post_channel(
+                &GuildId::new("G1".to_owned()),
+                &ChannelPost {
+                    name: "general".to_owned(),
+                    channel_type: ChannelType::GuildText,
+                    parent_id: None,
+                    permission_overwrites: Vec::new(),
+                    available_tags: Vec::new(),
+                },
+            )
             .await
-            .map_err(Error::Request)?;
-        trace!("response: {}", response);
-        serde_json::from_str(&response).map_err(Error::Schema)
-    }
-}
-
-pub async fn get_channel(token: &BotToken, channel: &ChannelId) -> Result<ChannelGet, Error> {
-    get_method(
-        token,
-        &format!("{}/channels/{}", DISCORD_ENDPOINT_COMMON, channel.0),
-    )
-    .await
-}
-
-pub async fn start_thread(
-    token: &BotToken,
-    channel: &ChannelId,
-    message_id: &MessageId,
-    name: &str,
-) -> Result<ChannelGet, Error> {
-    post_method_json(
-        token,
-        &format!(
-            "{}/channels/{}/messages/{}/threads",
-            DISCORD_ENDPOINT_COMMON, channel.0, message_id.0
-        ),
-        json!({
-            "name": name,
-        }),
-    )
-    .await
-}
-
-pub async fn get_channel(token: &BotToken, channel: &ChannelId) -> Result<ChannelGet, Error> {
-    get_method(
-        token,
-        &format!("{}/channels/{}", DISCORD_ENDPOINT_COMMON, channel.0),
-    )
-    .await
-}
-
-pub async fn archive_channel(token: &BotToken, channel: &ChannelId) -> Result<ChannelGet, Error> {
-    patch_method_json(
-        token,
-        &format!("{}/channels/{}", DISCORD_ENDPOINT_COMMON, channel.0),
-        &json!({"archived": true}),
-    )
-    .await
+            .unwrap();
+        assert_eq!(channel.id.to_string(), "C1");
+        assert_eq!(
+            server.await.unwrap(),
+            ["POST /guilds/G1/channels HTTP/1.1"; 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn paused_governors_hold_requests_back() {
+        let governor = RateGovernor::new(DISCORD_GLOBAL_RATE_LIMIT);
+        governor.pause(Duration::from_millis(100));
+        let start = Instant::now();
+        governor.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
 }
@@ -1,27 +1,41 @@
-#![feature(let_chains)]
 use anyhow::Context;
 use discord::ChannelGet;
 use futures::StreamExt;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
 use slack::Message;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use std::{
-    borrow::Borrow,
-    collections::{HashMap, HashSet},
-    ops::Deref,
+    borrow::{Borrow, Cow},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
 };
-use tokio::time::sleep;
 
+use crate::archive::{ArchiveError, ExportArchive};
+use crate::sink::MessageSink;
 use tracing::{debug, info, warn};
-use zip::ZipArchive;
-
-use crate::discord::archive_channel;
+use unicode_normalization::UnicodeNormalization;
 
+pub mod archive;
+pub mod blocks;
+pub mod checkpoint;
 pub mod discord;
+pub mod markdown;
+pub mod sink;
 pub mod slack;
+pub mod slack_api;
+pub mod source;
+pub mod template;
 
 pub struct Db {
     pub pool: sqlx::Pool<sqlx::Sqlite>,
     http_client: reqwest::Client,
+    revalidate_files: bool,
+    cache_max_bytes: Option<i64>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -36,6 +50,8 @@ pub enum DbError {
     NoCntentType,
     #[error("invalid content type")]
     InvalidContentType,
+    #[error("download failed with status {0}")]
+    DownloadStatus(u16),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
@@ -43,60 +59,327 @@ pub struct FileRow {
     pub url: String,
     pub inner: Vec<u8>,
     pub mime: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+pub struct DbOptions {
+    /// How long a connection waits on a locked database before failing.
+    pub busy_timeout: Duration,
+    /// Refuse every write, for inspecting a migration without touching it.
+    pub read_only: bool,
+    /// Check cached files against their `ETag`/`Last-Modified` before using them. Export URLs
+    /// don't change, files read through the Web API may.
+    pub revalidate_files: bool,
+    /// Connections kept open at most. SQLite serializes writers even in WAL mode, so a few
+    /// are plenty; more only wait on each other for the write lock.
+    pub max_connections: u32,
+    /// Bytes of cached files kept at most. Storing a file evicts the least recently used
+    /// others past this.
+    pub cache_max_bytes: Option<u64>,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            read_only: false,
+            revalidate_files: false,
+            max_connections: 4,
+            cache_max_bytes: None,
+        }
+    }
 }
 
+/// Version of the schema the `migrations` of this binary produce, recorded in `meta`.
+pub const SCHEMA_VERSION: i64 = 12;
+
 impl Db {
-    pub async fn new(url: &str) -> Result<Self, sqlx::Error> {
+    pub async fn new(url: &str) -> Result<Self, anyhow::Error> {
+        Self::new_with_options(url, &DbOptions::default()).await
+    }
+
+    /// Connect in WAL mode, so that cache inserts don't lock out concurrent readers.
+    /// Refuses databases migrated by a newer binary.
+    pub async fn new_with_options(url: &str, options: &DbOptions) -> Result<Self, anyhow::Error> {
         info!("connect db: {}", url);
-        let pool = sqlx::sqlite::SqlitePool::connect(url).await?;
+        let connect_options =
+            SqliteConnectOptions::from_str(url)?.busy_timeout(options.busy_timeout);
+        // switching the journal mode is a write, the file keeps whatever mode it has
+        let connect_options = if options.read_only {
+            connect_options.read_only(true)
+        } else {
+            connect_options.journal_mode(SqliteJournalMode::Wal)
+        };
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(options.max_connections.max(1))
+            .connect_with(connect_options)
+            .await?;
         let http_client = reqwest::Client::new();
-        Ok(Self { pool, http_client })
+        let db = Self {
+            pool,
+            http_client,
+            revalidate_files: options.revalidate_files,
+            cache_max_bytes: options
+                .cache_max_bytes
+                .map(|bytes| bytes.min(i64::MAX as u64) as i64),
+        };
+        if let Some(version) = db.schema_version().await? {
+            if version > SCHEMA_VERSION {
+                anyhow::bail!(
+                    "database schema {} is newer than {} of this binary",
+                    version,
+                    SCHEMA_VERSION
+                );
+            }
+        }
+        Ok(db)
     }
 
-    pub async fn fetch_file(&self, url: &str) -> Result<FileRow, DbError> {
-        let row = sqlx::query_as!(FileRow, "select * from files where url = ?", url)
+    /// Throwaway database with the schema applied, for sinks that don't need to resume.
+    pub async fn in_memory() -> Result<Self, anyhow::Error> {
+        info!("connect in-memory db");
+        // every connection to sqlite::memory: is a separate database, so keep exactly one alive
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
+        let http_client = reqwest::Client::new();
+        Ok(Self {
+            pool,
+            http_client,
+            revalidate_files: false,
+            cache_max_bytes: None,
+        })
+    }
+
+    /// Schema version recorded in `meta`, `None` for databases from before it existed.
+    pub async fn schema_version(&self) -> Result<Option<i64>, sqlx::Error> {
+        let has_meta = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from sqlite_master where type = 'table' and name = 'meta'"#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_meta == 0 {
+            return Ok(None);
+        }
+        let version = sqlx::query_scalar!("select value from meta where key = 'schema_version'")
             .fetch_optional(&self.pool)
+            .await?;
+        Ok(version.and_then(|version| version.parse().ok()))
+    }
+
+    /// Messages of a Slack channel recorded as posted.
+    pub async fn posted_count(&self, slack_channel_id: &str) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"select count(distinct slack_ts) as "count!: i64" from posts where slack_channel_id = ?"#,
+            slack_channel_id
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Latest Slack message of a channel recorded as posted.
+    pub async fn last_posted_ts(
+        &self,
+        slack_channel_id: &str,
+    ) -> Result<Option<slack::TimeStamp>, sqlx::Error> {
+        let posted = sqlx::query_scalar!(
+            "select slack_ts from posts where slack_channel_id = ?",
+            slack_channel_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        // stored without zero padding, so they do not sort as text
+        Ok(posted
+            .iter()
+            .filter_map(|ts| slack::TimeStamp::parse(ts).ok())
+            .max())
+    }
+
+    /// Whether the body of `url` is in the `files` cache.
+    pub async fn is_cached(&self, url: &str) -> Result<bool, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"select count(*) as "count!: i64" from files where url = ?"#,
+            url
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count > 0)
+    }
+
+    /// Bytes of file bodies held in the `files` cache.
+    pub async fn cache_size_bytes(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(r#"select coalesce(sum(length(inner)), 0) as "size!: i64" from files"#)
+            .fetch_one(&self.pool)
             .await
-            .map_err(DbError::GetSql)?;
-        if let Some(row) = row {
+    }
+
+    /// Size of the whole database file, including pages freed but not yet vacuumed.
+    pub async fn size_bytes(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "select page_count * page_size from pragma_page_count(), pragma_page_size()",
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Drop every cached file. `posts` is left untouched so resuming keeps working.
+    pub async fn clear_cache(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!("delete from files")
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Rebuild the database file to give space freed by deletes back to the filesystem.
+    pub async fn vacuum(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("vacuum").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn fetch_file(&self, url: &str) -> Result<FileRow, DbError> {
+        let row = sqlx::query_as!(
+            FileRow,
+            "select url, inner, mime, etag, last_modified from files where url = ?",
+            url
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(DbError::GetSql)?;
+        let mut request = self.http_client.get(url);
+        if let Some(mut row) = row {
             debug!("{} found in db", url);
-            Ok(row)
+            self.touch_file(url).await?;
+            // rows cached before normalization may still carry parameters
+            row.mime = normalize_mime(&row.mime).ok_or(DbError::InvalidContentType)?;
+            if !self.revalidate_files || (row.etag.is_none() && row.last_modified.is_none()) {
+                return Ok(row);
+            }
+            if let Some(etag) = &row.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &row.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+            let response = request.send().await.map_err(DbError::FetchFromUrl)?;
+            if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                debug!("{} not modified", url);
+                return Ok(row);
+            }
+            info!("{} changed, replace cached copy", url);
+            self.store_file(url, response).await
         } else {
             debug!("download {}", url);
-            let response = self
-                .http_client
-                .get(url)
-                .send()
-                .await
-                .map_err(DbError::FetchFromUrl)?;
-            let mime = response
-                .headers()
-                .get("content-type")
-                .ok_or(DbError::NoCntentType)?
-                .to_str()
-                .map_err(|_| DbError::InvalidContentType)?
-                .to_owned();
-            let bytes = response
-                .bytes()
-                .await
-                .map_err(DbError::FetchFromUrl)?
-                .to_vec();
-            sqlx::query!(
-                r#"insert into files (url, inner, mime) values (?, ?, ?)"#,
-                url,
-                bytes,
-                mime
-            )
+            let response = request.send().await.map_err(DbError::FetchFromUrl)?;
+            self.store_file(url, response).await
+        }
+    }
+
+    /// Mark the cached `url` as used now, for `cache_max_bytes`.
+    async fn touch_file(&self, url: &str) -> Result<(), DbError> {
+        if self.cache_max_bytes.is_none() {
+            return Ok(());
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query!("update files set last_accessed = ? where url = ?", now, url)
             .execute(&self.pool)
             .await
             .map_err(DbError::InsertSql)?;
-            Ok(FileRow {
-                url: url.to_owned(),
-                inner: bytes,
-                mime,
-            })
+        Ok(())
+    }
+
+    /// Delete the least recently used files but `keep` until the cache fits `cache_max_bytes`.
+    async fn evict_files(&self, keep: &str) -> Result<(), DbError> {
+        let Some(max_bytes) = self.cache_max_bytes else {
+            return Ok(());
+        };
+        // running total from the newest file down, everything past the cap goes
+        let evicted = sqlx::query!(
+            r#"delete from files where url in (
+                select url from (
+                    select url, sum(length(inner)) over (order by last_accessed desc, rowid desc) as total
+                    from files
+                ) where total > ? and url != ?
+            )"#,
+            max_bytes,
+            keep
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(DbError::InsertSql)?
+        .rows_affected();
+        if evicted > 0 {
+            debug!("evicted {} cached files", evicted);
         }
+        Ok(())
     }
+
+    async fn store_file(&self, url: &str, response: reqwest::Response) -> Result<FileRow, DbError> {
+        // expired links answer with an HTML error page, which must not be cached
+        if !response.status().is_success() {
+            warn!("download {} failed with {}", url, response.status());
+            return Err(DbError::DownloadStatus(response.status().as_u16()));
+        }
+        let headers = response.headers();
+        let mime = headers
+            .get("content-type")
+            .ok_or(DbError::NoCntentType)?
+            .to_str()
+            .map_err(|_| DbError::InvalidContentType)?;
+        let mime = normalize_mime(mime).ok_or(DbError::InvalidContentType)?;
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(DbError::FetchFromUrl)?
+            .to_vec();
+        let now = chrono::Utc::now().timestamp_millis();
+        sqlx::query!(
+            r#"insert or replace into files (url, inner, mime, etag, last_modified, last_accessed) values (?, ?, ?, ?, ?, ?)"#,
+            url,
+            bytes,
+            mime,
+            etag,
+            last_modified,
+            now
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(DbError::InsertSql)?;
+        self.evict_files(url).await?;
+        Ok(FileRow {
+            url: url.to_owned(),
+            inner: bytes,
+            mime,
+            etag,
+            last_modified,
+        })
+    }
+}
+
+/// Reduce a content type like `Text/Plain; charset=utf-8` to `text/plain`, or `None` if it is
+/// not of the form `type/subtype`.
+fn normalize_mime(content_type: &str) -> Option<String> {
+    let essence = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    let (type_, subtype) = essence.split_once('/')?;
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    (is_token(type_) && is_token(subtype)).then_some(essence)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
@@ -110,56 +393,444 @@ pub struct File {
 #[serde(transparent)]
 pub struct ChannelConfig(HashMap<String, String>);
 
+impl FromIterator<(String, String)> for ChannelConfig {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Category that DM channels land in unless the config maps them explicitly.
+pub const DM_CATEGORY: &str = "DMs";
+
+impl ChannelConfig {
+    /// Slack channel names the config maps.
+    pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    fn category_of(&self, channel: &SlackChannel) -> Option<&str> {
+        match self.0.get(&channel.name) {
+            Some(category) => Some(category),
+            None if channel.kind == ChannelKind::DirectMessage => Some(DM_CATEGORY),
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProvisionError {
+    #[error(
+        "the guild is at Discord's limit of 500 channels, remove unused ones or leave channels out of the config"
+    )]
+    ChannelLimit,
+    #[error(
+        "category {0} is at Discord's limit of 50 channels, spread its channels across more categories in the config"
+    )]
+    CategoryLimit(String),
+    #[error(
+        "Discord rejects the channel name {name} and its sanitized form {suggestion}, rename the channel with --channel-prefix or --channel-suffix"
+    )]
+    InvalidChannelName { name: String, suggestion: String },
+    #[error("guild {0} not found, the bot is not in it or GUILD_ID is wrong")]
+    GuildUnavailable(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PostError {
+    #[error("thread {slack_ts} of a reply in {channel} was never posted")]
+    ThreadParentMissing {
+        slack_ts: slack::TimeStamp,
+        channel: String,
+    },
+    #[error("Discord appears unavailable, {failures} posts failed in a row")]
+    Unavailable { failures: usize },
+}
+
+/// Posts failed in a row over a whole run, shared by every channel through `PostOptions`.
+#[derive(Debug)]
+pub struct FailureBudget {
+    limit: usize,
+    consecutive: AtomicUsize,
+}
+
+impl FailureBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            consecutive: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count the outcome of one post. Any success starts the count over, and the failure
+    /// reaching `limit` is wrapped in [`PostError::Unavailable`].
+    fn record<T>(&self, result: Result<T, anyhow::Error>) -> Result<T, anyhow::Error> {
+        match result {
+            Ok(value) => {
+                self.consecutive.store(0, Ordering::Relaxed);
+                Ok(value)
+            }
+            Err(e) => {
+                let failures = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= self.limit {
+                    Err(e.context(PostError::Unavailable { failures }))
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+impl PostError {
+    /// Whether `error` is the run giving up on Discord, which no channel can get past.
+    pub fn is_unavailable(error: &anyhow::Error) -> bool {
+        matches!(
+            error.downcast_ref::<PostError>(),
+            Some(PostError::Unavailable { .. })
+        )
+    }
+}
+
+/// Confirm the bot can see `guild`, whose channel list would come back empty otherwise.
+async fn check_guild(
+    client: &discord::DiscordClient,
+    guild: &discord::GuildId,
+) -> Result<(), anyhow::Error> {
+    match client.get_guild(guild).await {
+        Ok(info) => {
+            info!("migrate into guild {} ({})", info.name, info.id);
+            Ok(())
+        }
+        Err(discord::Error::Status {
+            status: 400 | 403 | 404,
+            ..
+        }) => Err(ProvisionError::GuildUnavailable(guild.as_str().to_owned()).into()),
+        Err(e) => Err(e).with_context(|| "get discord guild"),
+    }
+}
+
+/// Turn Discord's rejection of a channel over a channel limit into a [`ProvisionError`].
+fn channel_limit_error(error: discord::Error, category: Option<&str>) -> anyhow::Error {
+    match (error.json_code(), category) {
+        (Some(discord::ERROR_MAX_GUILD_CHANNELS), _) => ProvisionError::ChannelLimit.into(),
+        (_, Some(category)) if error.is_category_full() => {
+            ProvisionError::CategoryLimit(category.to_owned()).into()
+        }
+        _ => error.into(),
+    }
+}
+
+/// Find or create every category of `categories`. Categories are looked up by the id recorded
+/// in `db` first, so one renamed on Discord keeps being used, then by name among `deployed`.
+/// With `position`, they are then moved next to each other from that position on, by name.
+#[allow(clippy::too_many_arguments)]
 async fn provision_channel_categories(
+    client: &discord::DiscordClient,
     guild: &discord::GuildId,
-    token: &discord::BotToken,
+    db: Option<&Db>,
+    deployed: &[ChannelGet],
     categories: &HashSet<&str>,
+    dedicated: bool,
+    position: Option<u32>,
 ) -> Result<HashMap<String, discord::ChannelId>, anyhow::Error> {
-    let mut deployed_categories = discord::get_channels(guild, token)
-        .await?
-        .into_iter()
+    let deployed_ids = deployed
+        .iter()
+        .filter(|channel| channel.channel_type == discord::ChannelType::GuildCategory)
+        .map(|channel| &channel.id)
+        .collect::<HashSet<_>>();
+    let mut recorded = HashMap::new();
+    if let Some(db) = db {
+        let guild_id = guild.as_str();
+        let rows = sqlx::query!(
+            "select name, discord_id from categories where guild_id = ?",
+            guild_id
+        )
+        .fetch_all(&db.pool)
+        .await
+        .with_context(|| "read recorded categories")?;
+        for row in rows {
+            let id = discord::ChannelId::from(row.discord_id);
+            // a category deleted on Discord is looked up by name again
+            if deployed_ids.contains(&id) {
+                recorded.insert(row.name, id);
+            }
+        }
+    }
+    let mut by_name = deployed
+        .iter()
         .filter(|channel| {
-            channel.channel_type == discord::ChannelType::GuildCategory
+            !dedicated
+                && channel.channel_type == discord::ChannelType::GuildCategory
                 && categories.contains(&channel.name.borrow())
         })
-        .map(|channel| (channel.name, channel.id))
+        .map(|channel| (channel.name.clone(), channel.id.clone()))
         .collect::<HashMap<_, _>>();
 
+    let mut provisioned = HashMap::new();
     for category in categories {
-        if !deployed_categories.contains_key(*category) {
-            let channel = discord::post_channel(
-                guild,
-                token,
-                &discord::ChannelPost {
-                    name: category.deref().to_owned(),
-                    channel_type: discord::ChannelType::GuildCategory,
-                    parent_id: None,
-                },
+        let id = if let Some(id) = recorded.remove(*category) {
+            debug!("found recorded category {}", category);
+            id
+        } else if let Some(id) = by_name.remove(*category) {
+            info!("found existing category {}", category);
+            id
+        } else {
+            let channel = client
+                .post_channel(
+                    guild,
+                    &discord::ChannelPost {
+                        name: (*category).to_owned(),
+                        channel_type: discord::ChannelType::GuildCategory,
+                        parent_id: None,
+                        permission_overwrites: Vec::new(),
+                        available_tags: Vec::new(),
+                    },
+                )
+                .await
+                .map_err(|e| channel_limit_error(e, None))
+                .with_context(|| format!("create category {}", category))?;
+            info!("created category {}", category);
+            channel.id
+        };
+        if let Some(db) = db {
+            let guild_id = guild.as_str();
+            let category_id = id.to_string();
+            sqlx::query!(
+                "insert or replace into categories (guild_id, name, discord_id) values (?, ?, ?)",
+                guild_id,
+                category,
+                category_id
             )
+            .execute(&db.pool)
+            .await
+            .with_context(|| format!("record category {}", category))?;
+        }
+        provisioned.insert((*category).to_owned(), id);
+    }
+
+    if let Some(base) = position {
+        let positions = provisioned
+            .iter()
+            .sorted_by_key(|(name, _)| *name)
+            .zip(base..)
+            .map(|((_, id), position)| (id, position))
+            .collect::<Vec<_>>();
+        if !positions.is_empty() {
+            client
+                .set_channel_positions(guild, &positions)
+                .await
+                .with_context(|| "move categories together")?;
+            info!("moved {} categories to {}", positions.len(), base);
+        }
+    }
+
+    Ok(provisioned)
+}
+
+/// Discord rejects message content longer than this.
+const MESSAGE_CONTENT_MAX_CHARS: usize = 2000;
+
+/// Members of `channel` as one or more messages, each within Discord's content limit.
+fn render_member_roster(
+    channel: &SlackChannel,
+    users: &HashMap<String, slack::User>,
+) -> Vec<String> {
+    let mut messages = vec![format!("Members of #{} on Slack:", channel.name)];
+    for member in &channel.members {
+        let line = format!(
+            "- {}",
+            users
+                .get(member)
+                .map(|user| user.readable_name())
+                .unwrap_or(member)
+        );
+        let last = messages.last_mut().unwrap();
+        if last.chars().count() + 1 + line.chars().count() > MESSAGE_CONTENT_MAX_CHARS {
+            messages.push(line);
+        } else {
+            last.push('\n');
+            last.push_str(&line);
+        }
+    }
+    messages
+}
+
+/// The pinned first message of a channel under `ProvisionOptions::intro`.
+fn render_channel_intro(channel: &SlackChannel) -> String {
+    let mut intro = format!("Migrated from Slack channel **#{}**", channel.name);
+    let dates = channel
+        .messages
+        .first()
+        .zip(channel.messages.last())
+        .map(|(first, last)| {
+            let date = |message: &Message| message.ts().jtc_date().format("%Y-%m-%d").to_string();
+            (date(first), date(last))
+        });
+    match dates {
+        Some((first, last)) => intro.push_str(&format!(
+            "\n{} messages from {} to {}",
+            channel.messages.len(),
+            first,
+            last
+        )),
+        None => intro.push_str("\nIt had no messages"),
+    }
+    intro
+}
+
+/// Post and pin the intro of `channel` into the Discord channel or thread `target`. A forum
+/// gets the intro as a post of its own, pinned to the top of the forum.
+async fn post_channel_intro(
+    client: &discord::DiscordClient,
+    target: &discord::ChannelId,
+    forum: bool,
+    channel: &SlackChannel,
+) -> Result<(), anyhow::Error> {
+    let message = discord::MessagePost {
+        content: render_channel_intro(channel),
+        flags: 0,
+    };
+    if forum {
+        let post = client
+            .post_forum_thread(target, "about this channel", &[], &message, Vec::new())
             .await
-            .with_context(|| format!("create category {}", category))?;
-            deployed_categories.insert(category.deref().to_owned(), channel.id);
+            .with_context(|| format!("post intro of {}", channel.name))?;
+        client
+            .pin_forum_post(&post.id)
+            .await
+            .with_context(|| format!("pin intro of {}", channel.name))?;
+        return Ok(());
+    }
+    let intro = client
+        .post_message(target, &message, Vec::new())
+        .await
+        .with_context(|| format!("post intro of {}", channel.name))?;
+    client
+        .pin_message(target, &intro.id)
+        .await
+        .with_context(|| format!("pin intro of {}", channel.name))?;
+    Ok(())
+}
+
+/// How `provision_channels` lays out and creates channels.
+#[derive(Default)]
+pub struct ProvisionOptions<'a> {
+    /// Start every newly created channel that has members recorded in the export with a
+    /// message listing them.
+    pub roster: Option<&'a HashMap<String, slack::User>>,
+    /// Start every newly created channel with a pinned message telling which Slack channel it
+    /// was migrated from, over what dates and with how many messages.
+    pub intro: bool,
+    /// Category for channels archived on Slack, taking precedence over the config.
+    pub archived_category: Option<&'a str>,
+    /// Where the ids of provisioned categories are recorded for later runs.
+    pub db: Option<&'a Db>,
+    /// Put around the Slack name to name the Discord channel, both when creating it and when
+    /// looking for one created before. Discord lowercases the result itself.
+    pub channel_prefix: &'a str,
+    pub channel_suffix: &'a str,
+    /// Discord names of config categories, for categories named differently from the config.
+    pub category_names: Option<&'a HashMap<String, String>>,
+    /// Put before every Discord category name, renamed or not.
+    pub category_prefix: &'a str,
+    /// Only reuse categories recorded in `db` as created by an earlier run, never one found by
+    /// name, which may belong to the guild's own layout.
+    pub dedicated_categories: bool,
+    /// Slack channels created as forums rather than text channels.
+    pub forum_channels: Option<&'a BTreeSet<String>>,
+    /// Tags forums are created with.
+    pub forum_tags: &'a [ForumTagRule],
+    /// Position of the first category in the channel list, the others following it in name
+    /// order, so they stay together instead of interleaving with the guild's own.
+    pub category_position: Option<u32>,
+}
+
+impl<'a> ProvisionOptions<'a> {
+    fn category_of<'b>(
+        &'b self,
+        config: &'b ChannelConfig,
+        channel: &SlackChannel,
+    ) -> Option<&'b str> {
+        match self.archived_category {
+            Some(category) if channel.archived => Some(category),
+            _ => config.category_of(channel),
         }
-        info!("provisioned category {}", category);
     }
 
-    Ok(deployed_categories)
+    /// Name of the Discord category for the config category `category`, used both to find an
+    /// existing one and to create it, so reruns never create it twice.
+    fn category_name(&self, category: &str) -> String {
+        let renamed = self
+            .category_names
+            .and_then(|names| names.get(category))
+            .map(String::as_str)
+            .unwrap_or(category);
+        format!("{}{}", self.category_prefix, renamed)
+    }
+
+    fn is_forum(&self, channel: &SlackChannel) -> bool {
+        self.forum_channels
+            .is_some_and(|forums| forums.contains(&channel.name))
+    }
+
+    fn discord_name(&self, channel: &SlackChannel) -> String {
+        format!(
+            "{}{}{}",
+            self.channel_prefix, channel.name, self.channel_suffix
+        )
+    }
 }
 
+/// Create the Discord channels missing from `guild`, and return the Discord channel of every
+/// configured one keyed by its Slack name.
 pub async fn provision_channels(
+    client: &discord::DiscordClient,
     guild: &discord::GuildId,
-    token: &discord::BotToken,
-    channels: Vec<slack::Channel>,
+    channels: &[SlackChannel],
     config: &ChannelConfig,
+    options: &ProvisionOptions<'_>,
 ) -> Result<HashMap<String, ChannelGet>, anyhow::Error> {
+    check_guild(client, guild).await?;
+    // hidden channels let the bot in by its user id
+    let bot = if channels
+        .iter()
+        .any(|channel| channel.kind != ChannelKind::Public)
+    {
+        Some(
+            client
+                .get_current_user()
+                .await
+                .with_context(|| "get the bot user")?,
+        )
+    } else {
+        None
+    };
+    let mut category_names = config
+        .0
+        .values()
+        .map(|category| category.as_str())
+        .collect::<HashSet<_>>();
+    category_names.extend(
+        channels
+            .iter()
+            .filter_map(|channel| options.category_of(config, channel)),
+    );
+    let deployed = client
+        .get_channels(guild)
+        .await
+        .with_context(|| "get discord channels")?;
+    let category_names = category_names
+        .into_iter()
+        .map(|category| options.category_name(category))
+        .collect::<HashSet<_>>();
     let categories = provision_channel_categories(
+        client,
         guild,
-        token,
-        &config
-            .0
-            .values()
-            .map(|category| category.as_str())
-            .collect(),
+        options.db,
+        &deployed,
+        &category_names.iter().map(String::as_str).collect(),
+        options.dedicated_categories,
+        options.category_position,
     )
     .await?;
 
@@ -168,291 +839,3525 @@ pub async fn provision_channels(
         .map(|(x, y)| (y, x))
         .collect::<HashMap<_, _>>();
 
-    let mut channels_deployed = discord::get_channels(guild, token)
-        .await
-        .with_context(|| "get discord channels")?
+    let mut channels_deployed = deployed
         .into_iter()
         .filter(|channel| {
-            channel.channel_type == discord::ChannelType::GuildText
-                && channel
-                    .parent_id
-                    .as_ref()
-                    .map(|id| categories_reverse.contains_key(&id))
-                    .unwrap_or(false)
+            matches!(
+                channel.channel_type,
+                discord::ChannelType::GuildText | discord::ChannelType::GuildForum
+            ) && channel
+                .parent_id
+                .as_ref()
+                .map(|id| categories_reverse.contains_key(&id))
+                .unwrap_or(false)
         })
         .map(|channel| (channel.name.to_owned(), channel))
         .collect::<HashMap<_, _>>();
 
     debug!("deployed :{:#?} ", channels_deployed);
 
+    let mut provisioned = HashMap::new();
     for channel in channels {
-        if let Some(_deployed_channel) = channels_deployed.get(&channel.name) {
+        let discord_name = options.discord_name(channel);
+        let sanitized_name = sanitize_channel_name(&discord_name);
+        // a name Discord rejected was created sanitized by an earlier run
+        if let Some(deployed_channel) = channels_deployed
+            .get(&discord_name)
+            .or_else(|| channels_deployed.get(&sanitized_name))
+        {
+            provisioned.insert(channel.name.clone(), deployed_channel.clone());
             continue;
         }
 
-        if let Some(category_name) = config.0.get(&channel.name) {
+        if let Some(category_name) = options.category_of(config, channel) {
+            let category_name = &options.category_name(category_name);
             let parent_id = categories
                 .get(category_name)
                 .with_context(|| format!("category {} yet deployed", category_name))?;
-            let channel = discord::post_channel(
-                guild,
-                token,
-                &discord::ChannelPost {
-                    name: channel.name.clone(),
-                    channel_type: discord::ChannelType::GuildText,
+            let forum = options.is_forum(channel);
+            let post = |name: &str| {
+                let channel_post = discord::ChannelPost {
+                    name: name.to_owned(),
+                    channel_type: if forum {
+                        discord::ChannelType::GuildForum
+                    } else {
+                        discord::ChannelType::GuildText
+                    },
                     parent_id: Some(parent_id.clone()),
-                },
-            )
-            .await
+                    permission_overwrites: match (channel.kind, &bot) {
+                        (ChannelKind::Private | ChannelKind::DirectMessage, Some(bot)) => {
+                            discord::PermissionOverwrite::hide_from_everyone(guild, &bot.id)
+                        }
+                        _ => Vec::new(),
+                    },
+                    available_tags: if forum {
+                        options
+                            .forum_tags
+                            .iter()
+                            .map(|tag| discord::ForumTagPost {
+                                name: tag.name.clone(),
+                            })
+                            .collect()
+                    } else {
+                        Vec::new()
+                    },
+                };
+                async move { client.post_channel(guild, &channel_post).await }
+            };
+            let deployed = match post(&discord_name).await {
+                Err(e) if e.is_invalid_name() && sanitized_name != discord_name => {
+                    warn!(
+                        "Discord rejects the channel name {}, retry as {}",
+                        discord_name, sanitized_name
+                    );
+                    post(&sanitized_name).await
+                }
+                result => result,
+            }
+            .map_err(|e| {
+                if e.is_invalid_name() {
+                    ProvisionError::InvalidChannelName {
+                        name: discord_name.clone(),
+                        suggestion: sanitized_name.clone(),
+                    }
+                    .into()
+                } else {
+                    channel_limit_error(e, Some(category_name))
+                }
+            })
             .with_context(|| format!("deploy channel {}", channel.name))?;
-            channels_deployed.insert(channel.name.clone(), channel);
+            if options.intro {
+                post_channel_intro(client, &deployed.id, forum, channel).await?;
+            }
+            if let (Some(users), false) = (options.roster, channel.members.is_empty()) {
+                for content in render_member_roster(channel, users) {
+                    let message = discord::MessagePost { content, flags: 0 };
+                    // forums only hold posts, the roster becomes one of its own
+                    let posted = if forum {
+                        client
+                            .post_forum_thread(&deployed.id, "members", &[], &message, Vec::new())
+                            .await
+                            .map(|_| ())
+                    } else {
+                        client
+                            .post_message(&deployed.id, &message, Vec::new())
+                            .await
+                            .map(|_| ())
+                    };
+                    posted.with_context(|| format!("post member roster of {}", channel.name))?;
+                }
+            }
+            // Discord may have adjusted the name, later channels compare against what was asked
+            channels_deployed.insert(discord_name, deployed.clone());
+            provisioned.insert(channel.name.clone(), deployed);
         } else {
             warn!("unconfigured channel {}", channel.name);
         }
     }
-    Ok(channels_deployed)
+    Ok(provisioned)
+}
+
+/// Like [`provision_channels`], but every configured Slack channel becomes a thread of the
+/// text channel `hub`, started on a message naming the channel. `hub` is created outside any
+/// category when missing. Threads are recorded in `hub_threads` so a rerun keeps posting into
+/// them. Threads cannot hold threads, so replies have to be posted with
+/// `PostOptions::inline_threads`.
+pub async fn provision_hub_threads(
+    client: &discord::DiscordClient,
+    guild: &discord::GuildId,
+    channels: &[SlackChannel],
+    config: &ChannelConfig,
+    hub: &str,
+    options: &ProvisionOptions<'_>,
+) -> Result<HashMap<String, ChannelGet>, anyhow::Error> {
+    check_guild(client, guild).await?;
+    let deployed = client
+        .get_channels(guild)
+        .await
+        .with_context(|| "get discord channels")?;
+    let hub_channel = match deployed.into_iter().find(|channel| {
+        channel.channel_type == discord::ChannelType::GuildText && channel.name == hub
+    }) {
+        Some(channel) => {
+            info!("found existing hub channel {}", hub);
+            channel
+        }
+        None => {
+            let channel = client
+                .post_channel(
+                    guild,
+                    &discord::ChannelPost {
+                        name: hub.to_owned(),
+                        channel_type: discord::ChannelType::GuildText,
+                        parent_id: None,
+                        permission_overwrites: Vec::new(),
+                        available_tags: Vec::new(),
+                    },
+                )
+                .await
+                .map_err(|e| channel_limit_error(e, None))
+                .with_context(|| format!("create hub channel {}", hub))?;
+            info!("created hub channel {}", hub);
+            channel
+        }
+    };
+    let hub_id = hub_channel.id.to_string();
+
+    let mut recorded = HashMap::new();
+    if let Some(db) = options.db {
+        let rows = sqlx::query!(
+            "select slack_channel_id, thread_id from hub_threads where hub_id = ?",
+            hub_id
+        )
+        .fetch_all(&db.pool)
+        .await
+        .with_context(|| "read recorded hub threads")?;
+        recorded.extend(
+            rows.into_iter()
+                .map(|row| (row.slack_channel_id, row.thread_id)),
+        );
+    }
+
+    let mut provisioned = HashMap::new();
+    for channel in channels {
+        if options.category_of(config, channel).is_none() {
+            warn!("unconfigured channel {}", channel.name);
+            continue;
+        }
+        if let Some(thread_id) = recorded.remove(&channel.id) {
+            match client.get_channel(&thread_id.clone().into()).await {
+                Ok(thread) => {
+                    debug!("found recorded hub thread {}", channel.name);
+                    provisioned.insert(channel.name.clone(), thread);
+                    continue;
+                }
+                // a thread deleted on Discord is started again
+                Err(e) if e.is_not_found() => (),
+                Err(e) => {
+                    return Err(e).with_context(|| format!("get hub thread of {}", channel.name))
+                }
+            }
+        }
+        let discord_name = options.discord_name(channel);
+        let seed = client
+            .post_message(
+                &hub_channel.id,
+                &discord::MessagePost {
+                    content: format!("**#{}**", channel.name),
+                    flags: 0,
+                },
+                Vec::new(),
+            )
+            .await
+            .with_context(|| format!("post hub message of {}", channel.name))?;
+        let thread = client
+            .start_thread(&hub_channel.id, &seed.id, &discord_name)
+            .await
+            .with_context(|| format!("start hub thread of {}", channel.name))?;
+        info!("started hub thread {}", discord_name);
+        if let Some(db) = options.db {
+            let thread_id = thread.id.to_string();
+            sqlx::query!(
+                "insert or replace into hub_threads (slack_channel_id, hub_id, thread_id) values (?, ?, ?)",
+                channel.id,
+                hub_id,
+                thread_id
+            )
+            .execute(&db.pool)
+            .await
+            .with_context(|| format!("record hub thread of {}", channel.name))?;
+        }
+        if options.intro {
+            post_channel_intro(client, &thread.id, false, channel).await?;
+        }
+        if let (Some(users), false) = (options.roster, channel.members.is_empty()) {
+            for content in render_member_roster(channel, users) {
+                client
+                    .post_message(
+                        &thread.id,
+                        &discord::MessagePost { content, flags: 0 },
+                        Vec::new(),
+                    )
+                    .await
+                    .with_context(|| format!("post member roster of {}", channel.name))?;
+            }
+        }
+        provisioned.insert(channel.name.clone(), thread);
+    }
+    Ok(provisioned)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ChannelKind {
+    Public,
+    /// Created with `@everyone` denied from viewing it and the bot let in.
+    Private,
+    /// Hidden the same as [`ChannelKind::Private`].
+    DirectMessage,
 }
 
+#[derive(serde::Serialize)]
 pub struct SlackChannel {
     pub id: String,
     pub name: String,
+    pub kind: ChannelKind,
+    /// Slack user ids of the channel members.
+    pub members: Vec<String>,
+    pub archived: bool,
     pub messages: Vec<Message>,
 }
 
-pub fn get_channels_stream<R: std::io::Read + std::io::Seek>(
-    zip: &mut ZipArchive<R>,
-) -> Result<Vec<SlackChannel>, anyhow::Error> {
-    let channels: Vec<slack::Channel> = {
-        let entry = zip
-            .by_name("channels.json")
-            .with_context(|| "read channels.json")?;
-
-        serde_json::from_reader(entry).with_context(|| "parse channels.json")?
-    };
-
-    let mut channels = channels
-        .into_iter()
-        .map(|channel| {
-            let channel = SlackChannel {
-                id: channel.id.clone(),
-                name: channel.name,
-                messages: Vec::new(),
-            };
-            (channel.name.clone(), channel)
+pub(crate) fn dm_channel_name(members: &[String], users: &HashMap<String, slack::User>) -> String {
+    let mut names = members
+        .iter()
+        .map(|member| {
+            users
+                .get(member)
+                .map(|user| user.name.as_str())
+                .unwrap_or(member)
         })
-        .collect::<HashMap<_, _>>();
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    sanitize_channel_name(&format!("dm-{}", names.join("-")))
+}
 
-    for index in 0..zip.len() {
-        let mut entry = zip
-            .by_index(index)
-            .with_context(|| format!("get zip entry at {}", index))?;
-        let entry_name = String::from_utf8(entry.name_raw().to_owned())
-            .with_context(|| "read zip entry name as utf8".to_string())?;
-        if let &[channel_name, file_name] = &entry_name.split('/').collect::<Vec<_>>()[..] {
-            if file_name.is_empty() {
-                debug!("skip dir {}", entry_name);
-                continue;
+/// `name` lowercased, with anything but letters, digits, `-` and `_` turned into `-` and cut
+/// to the 100 characters Discord allows.
+fn sanitize_channel_name(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
             }
-            let mut messages: Vec<slack::Message> = serde_json::from_reader(&mut entry)
-                .with_context(|| format!("parse {}", entry_name))?;
-            channels
-                .get_mut(channel_name)
-                .with_context(|| format!("{} not found in channels.json", channel_name))?
-                .messages
-                .append(&mut messages);
-        } else {
-            debug!("skip entry {}", entry.name());
-        }
-    }
-
-    let sorted_channels = channels
-        .into_values()
-        .into_iter()
-        .map(|mut channel| {
-            channel.messages.sort_by_key(|message| match message {
-                slack::Message::Message { ts, .. } => ts.clone(),
-            });
-            channel
         })
-        .collect::<Vec<_>>();
-    Ok(sorted_channels)
+        .take(100)
+        .collect()
 }
 
-#[derive(Clone, PartialEq, Eq, sqlx::FromRow, Debug)]
-struct PostRecord {
-    id: String,
-    slack_channel_id: String,
-    discord_channel_id: String,
-    slack_ts: String,
-    discord_thread_id: Option<String>,
+/// Channel directory and file name of a zip entry `<dir>/<file>`. Entries written on Windows
+/// separate with `\`, and macOS stores names decomposed (NFD), so directories are compared in
+/// NFC. Directory entries come back with an empty file name.
+fn split_entry_name(entry_name: &str) -> Option<(String, String)> {
+    let entry_name = entry_name.replace('\\', "/");
+    match entry_name.split('/').collect::<Vec<_>>()[..] {
+        [dir, file] => Some((dir.nfc().collect(), file.to_owned())),
+        _ => None,
+    }
 }
 
-fn replace_slack_id_to_real_name(dict: &HashMap<String, String>, src: &str) -> String {
-    dict.iter()
-        .fold(src.to_owned(), |src, (from, to)| src.replace(from, to))
+/// A list file that only some exports carry, empty when missing.
+fn read_optional_list<A: ExportArchive + ?Sized, T: serde::de::DeserializeOwned>(
+    archive: &mut A,
+    file_name: &str,
+) -> Result<Vec<T>, ArchiveError> {
+    match archive.open(file_name).map_err(ArchiveError::Read)? {
+        Some(entry) => parse_entry(file_name, entry),
+        None => {
+            debug!("{} not found in archive", file_name);
+            Ok(Vec::new())
+        }
+    }
 }
 
-pub async fn post_channel(
-    db: &Db,
-    token: &discord::BotToken,
-    discord_channels: &HashMap<String, ChannelGet>,
-    channel: &SlackChannel,
-    users: &HashMap<String, slack::User>,
-) -> Result<(), anyhow::Error> {
-    let discord_channel = discord_channels
-        .get(&channel.name)
-        .with_context(|| format!("get discord_channel_id of {}", &channel.name))?;
-    let discord_channel_id = &discord_channel.id;
+fn parse_entry<T: serde::de::DeserializeOwned>(
+    entry_name: &str,
+    entry: impl std::io::Read,
+) -> Result<T, ArchiveError> {
+    serde_json::from_reader(entry).map_err(|source| ArchiveError::Parse {
+        entry: entry_name.to_owned(),
+        source,
+    })
+}
 
-    let user_id_to_real_name = users
-        .iter()
-        .map(|(_, user)| (user.id.clone(), user.readable_name().to_owned()))
-        .collect::<HashMap<_, _>>();
+/// Hand the messages of the day file `entry` to `each` as they are parsed, so a huge file is
+/// never held as a whole array on top of the channel it goes into.
+fn stream_messages(
+    entry_name: &str,
+    entry: impl std::io::Read,
+    each: impl FnMut(Message),
+) -> Result<(), ArchiveError> {
+    struct Messages<F>(F);
 
-    let mut reply_counts = HashMap::new();
+    impl<'de, F: FnMut(Message)> serde::de::Visitor<'de> for Messages<F> {
+        type Value = ();
 
-    for message in &channel.messages {
-        match message {
-            slack::Message::Message {
-                text,
-                files,
-                ts,
-                reply_count,
-                user,
-                thread_ts,
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("an array of messages")
+        }
+
+        fn visit_seq<S: serde::de::SeqAccess<'de>>(mut self, mut seq: S) -> Result<(), S::Error> {
+            while let Some(message) = seq.next_element()? {
+                (self.0)(message);
+            }
+            Ok(())
+        }
+    }
+
+    let parse_error = |source| ArchiveError::Parse {
+        entry: entry_name.to_owned(),
+        source,
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(std::io::BufReader::new(entry));
+    serde::Deserializer::deserialize_seq(&mut deserializer, Messages(each)).map_err(parse_error)?;
+    deserializer.end().map_err(parse_error)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadProgress {
+    pub entries_processed: usize,
+    pub entries_total: usize,
+    pub channels_populated: usize,
+}
+
+/// Which kinds of conversations besides public channels are loaded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadOptions {
+    /// Group DMs (`mpims.json`) and DMs (`dms.json`).
+    pub include_dms: bool,
+    /// Private channels (`groups.json`).
+    pub include_private: bool,
+}
+
+pub fn get_channels_stream<A: ExportArchive + ?Sized>(
+    archive: &mut A,
+    users: &HashMap<String, slack::User>,
+    options: &LoadOptions,
+    progress: &mut dyn FnMut(LoadProgress),
+) -> Result<Vec<SlackChannel>, ArchiveError> {
+    // partial exports may lack channels.json, their channels are then found by directory
+    let channels_json: Option<Vec<slack::Channel>> =
+        match archive.open("channels.json").map_err(ArchiveError::Read)? {
+            Some(entry) => Some(parse_entry("channels.json", entry)?),
+            None => {
+                warn!(
+                    "channels.json not found, channels are named after their directories, \
+                 without their ids, members, topics or archive state"
+                );
+                None
+            }
+        };
+    let derive_channels = channels_json.is_none();
+
+    // keyed by id, since names are not unique; directories map to ids through dir_ids.
+    // order keeps the list file order, so runs go through channels the same way every time
+    let mut channels = HashMap::new();
+    let mut dir_ids = HashMap::new();
+    let mut order = Vec::new();
+    let mut add_channel = |dir_name: String, channel: SlackChannel| {
+        let dir_name = dir_name.nfc().collect::<String>();
+        if let Some(previous) = dir_ids.insert(dir_name.clone(), channel.id.clone()) {
+            warn!(
+                "{} and {} share the directory {}, it is read as {}",
+                previous, channel.id, dir_name, channel.id
+            );
+        }
+        if channels.insert(channel.id.clone(), channel).is_none() {
+            order.push(dir_ids[&dir_name].clone());
+        }
+    };
+
+    for channel in channels_json.into_iter().flatten() {
+        add_channel(
+            channel.name.clone(),
+            SlackChannel {
+                id: channel.id,
+                name: channel.name,
+                kind: ChannelKind::Public,
+                members: channel.members,
+                archived: channel.is_archived,
+                messages: Vec::new(),
+            },
+        );
+    }
+
+    let mut skipped_dirs = HashSet::new();
+    for group in read_optional_list::<_, slack::Channel>(archive, "groups.json")? {
+        if !options.include_private {
+            skipped_dirs.insert(group.name.nfc().collect::<String>());
+            continue;
+        }
+        add_channel(
+            group.name.clone(),
+            SlackChannel {
+                id: group.id,
+                name: group.name,
+                kind: ChannelKind::Private,
+                members: group.members,
+                archived: group.is_archived,
+                messages: Vec::new(),
+            },
+        );
+    }
+
+    // mpim directories are named after the mpim, dm directories after the dm id
+    for file_name in ["mpims.json", "dms.json"] {
+        for dm in read_optional_list::<_, slack::DirectMessage>(archive, file_name)? {
+            let dir_name = dm.name.clone().unwrap_or_else(|| dm.id.clone());
+            if !options.include_dms {
+                skipped_dirs.insert(dir_name.nfc().collect::<String>());
+                continue;
+            }
+            add_channel(
+                dir_name,
+                SlackChannel {
+                    name: dm_channel_name(&dm.members, users),
+                    id: dm.id,
+                    kind: ChannelKind::DirectMessage,
+                    members: dm.members,
+                    archived: false,
+                    messages: Vec::new(),
+                },
+            );
+        }
+    }
+
+    let entry_names = archive.entry_names().map_err(ArchiveError::Read)?;
+    if derive_channels {
+        let dirs = entry_names
+            .iter()
+            .filter_map(|name| split_entry_name(name))
+            .map(|(dir, _)| dir)
+            .collect::<BTreeSet<_>>();
+        for dir in dirs {
+            if dir_ids.contains_key(&dir) || skipped_dirs.contains(&dir) {
+                continue;
+            }
+            debug!("derive channel {} from its directory", dir);
+            dir_ids.insert(dir.clone(), dir.clone());
+            order.push(dir.clone());
+            channels.insert(
+                dir.clone(),
+                SlackChannel {
+                    id: dir.clone(),
+                    name: dir,
+                    kind: ChannelKind::Public,
+                    members: Vec::new(),
+                    archived: false,
+                    messages: Vec::new(),
+                },
+            );
+        }
+        if channels.is_empty() {
+            return Err(ArchiveError::NotAnExport);
+        }
+    }
+
+    let mut populated = HashSet::new();
+    for (index, entry_name) in entry_names.iter().enumerate() {
+        progress(LoadProgress {
+            entries_processed: index,
+            entries_total: entry_names.len(),
+            channels_populated: populated.len(),
+        });
+        if let Some((channel_name, file_name)) = split_entry_name(entry_name) {
+            if file_name.is_empty() {
+                debug!("skip dir {}", entry_name);
+                continue;
+            }
+            if skipped_dirs.contains(&channel_name) {
+                debug!("skip excluded {}", entry_name);
+                continue;
+            }
+            let id = dir_ids
+                .get(&channel_name)
+                .ok_or_else(|| ArchiveError::UnknownChannel(channel_name.clone()))?;
+            let entry = archive
+                .open(entry_name)
+                .map_err(ArchiveError::Read)?
+                .ok_or_else(|| ArchiveError::Vanished(entry_name.clone()))?;
+            let messages = &mut channels
+                .get_mut(id)
+                .expect("every dir id has a channel")
+                .messages;
+            stream_messages(entry_name, entry, |message| messages.push(message))?;
+            populated.insert(id.clone());
+        } else {
+            debug!("skip entry {}", entry_name);
+        }
+    }
+    progress(LoadProgress {
+        entries_processed: entry_names.len(),
+        entries_total: entry_names.len(),
+        channels_populated: populated.len(),
+    });
+
+    Ok(order
+        .into_iter()
+        .filter_map(|id| channels.remove(&id))
+        .map(sort_messages)
+        .collect())
+}
+
+/// Channel directories of an export, for exports without `channels.json`. Directories of
+/// private channels and DMs listed in `groups.json`, `mpims.json` or `dms.json` are left out.
+pub fn channel_dirs<A: ExportArchive + ?Sized>(
+    archive: &mut A,
+) -> Result<BTreeSet<String>, ArchiveError> {
+    let mut listed = HashSet::new();
+    for group in read_optional_list::<_, slack::Channel>(archive, "groups.json")? {
+        listed.insert(group.name.nfc().collect::<String>());
+    }
+    for file_name in ["mpims.json", "dms.json"] {
+        for dm in read_optional_list::<_, slack::DirectMessage>(archive, file_name)? {
+            listed.insert(dm.name.unwrap_or(dm.id).nfc().collect::<String>());
+        }
+    }
+    Ok(archive
+        .entry_names()
+        .map_err(ArchiveError::Read)?
+        .iter()
+        .filter_map(|name| split_entry_name(name))
+        .map(|(dir, _)| dir)
+        .filter(|dir| !listed.contains(dir))
+        .collect())
+}
+
+/// Sort the messages of `channel` by ts. Of messages sharing a ts, repeats by the same user
+/// with the same text are dropped, the others get a collision counter so that `posts`
+/// records each of them.
+pub(crate) fn sort_messages(mut channel: SlackChannel) -> SlackChannel {
+    channel
+        .messages
+        .sort_by(|left, right| left.ts().cmp(right.ts()));
+    let loaded = channel.messages.len();
+    let mut messages: Vec<Message> = Vec::with_capacity(loaded);
+    let mut collisions = 0;
+    // messages kept so far with the ts of the current one, first of them in `group_start`
+    let mut group_start = 0;
+    for mut message in channel.messages {
+        if messages
+            .get(group_start)
+            .is_none_or(|first| first.ts() != message.ts())
+        {
+            group_start = messages.len();
+        }
+        let group = &messages[group_start..];
+        let Message::Message { text, .. } = &message;
+        if group.iter().any(|kept| {
+            let Message::Message {
+                text: kept_text, ..
+            } = kept;
+            kept.author() == message.author() && kept_text == text
+        }) {
+            continue;
+        }
+        if !group.is_empty() {
+            let Message::Message { ts, .. } = &mut message;
+            *ts = ts.with_collision(group.len() as u32);
+            collisions += 1;
+        }
+        messages.push(message);
+    }
+    if messages.len() < loaded {
+        debug!(
+            "dropped {} duplicated messages in {}",
+            loaded - messages.len(),
+            channel.name
+        );
+    }
+    if collisions > 0 {
+        warn!(
+            "{} messages in {} share a ts with another, they are told apart by a counter",
+            collisions, channel.name
+        );
+    }
+    channel.messages = messages;
+    channel
+}
+
+#[derive(Clone, PartialEq, Eq, sqlx::FromRow, Debug)]
+struct PostRecord {
+    id: String,
+    slack_channel_id: String,
+    discord_channel_id: String,
+    slack_ts: String,
+    discord_thread_id: Option<String>,
+}
+
+impl PostRecord {
+    /// Whether the message was posted into a thread, rather than being the start of one.
+    fn is_in_thread(&self) -> bool {
+        self.discord_thread_id
+            .as_ref()
+            .is_some_and(|thread| *thread != self.id)
+    }
+}
+
+static SLACK_USER_MENTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<@([UW][A-Z0-9]+)(?:\|[^>]*)?>").unwrap());
+
+/// Text Slack unfurls on its own, a lone link with or without a label.
+static SLACK_LONE_LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*<?https?://[^\s|>]+(?:\|[^>]*)?>?\s*$").unwrap());
+
+static SLACK_SPECIAL_MENTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<!(here|channel|everyone)(?:\|[^>]*)?>").unwrap());
+
+static SLACK_LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"<((?:https?|mailto):[^\s|>]+)(?:\|([^>]*))?>").unwrap());
+
+static DISCORD_MASS_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"@(everyone|here)").unwrap());
+
+fn replace_slack_id_to_real_name(dict: &HashMap<String, String>, src: &str) -> String {
+    // plain text only, `post_message` keeps Discord from turning them into pings
+    let src = SLACK_SPECIAL_MENTION.replace_all(src, "@$1");
+    let src = SLACK_USER_MENTION.replace_all(&src, |caps: &Captures| match dict.get(&caps[1]) {
+        // `NameStyle::Username` names already carry the `@`
+        Some(name) if name.starts_with('@') => name.clone(),
+        Some(name) => format!("@{}", name),
+        None => caps[0].to_owned(),
+    });
+    let src = rewrite_slack_links(&src);
+    // a zero-width space after the `@`, so these never ping even if `allowed_mentions` is lost
+    DISCORD_MASS_MENTION
+        .replace_all(&src, "@\u{200B}$1")
+        .into_owned()
+}
+
+/// Slack's `<url|label>` as a masked link, and as the bare url when there is no label or
+/// the label only repeats the url, with or without its scheme.
+fn rewrite_slack_links(src: &str) -> Cow<'_, str> {
+    SLACK_LINK.replace_all(src, |caps: &Captures| {
+        let url = &caps[1];
+        let bare = url
+            .strip_prefix("mailto:")
+            .or_else(|| url.split_once("://").map(|(_, rest)| rest))
+            .unwrap_or(url);
+        match caps.get(2).map(|label| label.as_str()) {
+            Some(label) if !label.is_empty() && label != url && label != bare => {
+                format!("[{}](<{}>)", label, url)
+            }
+            _ if url.starts_with("mailto:") => bare.to_owned(),
+            _ => url.to_owned(),
+        }
+    })
+}
+
+fn user_id_to_real_name(
+    users: &HashMap<String, slack::User>,
+    style: slack::NameStyle,
+) -> HashMap<String, String> {
+    users
+        .values()
+        .map(|user| (user.id.clone(), user.display_name(style)))
+        .collect()
+}
+
+/// Which messages of a channel a run posts. A large workspace can get its channels readable
+/// quickly with `SkipReplies`, then fill in the threads with a second run in `OnlyReplies`
+/// against the same database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThreadPass {
+    #[default]
+    All,
+    /// Channel messages and thread parents, no replies.
+    SkipReplies,
+    /// Only replies, into threads whose parents an earlier run posted.
+    OnlyReplies,
+}
+
+/// Whether a run posts files. A run with `SkipFiles` posts messages without downloading
+/// anything and records those that had files in `pending_files`. A later run with
+/// `OnlyFiles` on the same database posts those files after their messages, as messages
+/// already in `posts` are never posted again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilePass {
+    #[default]
+    All,
+    SkipFiles,
+    /// Only messages with files: unposted ones in full, and the files of ones a `SkipFiles`
+    /// run posted.
+    OnlyFiles,
+}
+
+fn has_hosted_files(files: &Option<Vec<slack::File>>) -> bool {
+    files.iter().flatten().any(|file| {
+        matches!(
+            file,
+            slack::File::Hosted {
+                url_private_download: Some(_),
                 ..
-            } => {
-                if let Some(reply_count) = reply_count {
-                    reply_counts.insert(ts, *reply_count);
+            }
+        )
+    })
+}
+
+/// Links to the hosted `files` that cannot be downloaded, the public permalink if the file
+/// was shared publicly.
+fn render_file_links(files: &Option<Vec<slack::File>>) -> String {
+    let mut out = String::new();
+    for file in files.iter().flatten() {
+        if let slack::File::Hosted {
+            title,
+            url_private_download: None,
+            permalink,
+            permalink_public,
+            ..
+        } = file
+        {
+            match permalink_public.as_ref().or(permalink.as_ref()) {
+                Some(link) => out.push_str(&format!("({} at {})\n", title, link)),
+                None => warn!("file {} has neither a download url nor a permalink", title),
+            }
+        }
+    }
+    out
+}
+
+/// Where a reply also sent to the channel (`thread_broadcast`) is posted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastMode {
+    /// In the thread and in the channel, as Slack shows it.
+    Both,
+    /// Only in the channel, noted as also in the thread.
+    Channel,
+}
+
+impl std::str::FromStr for BroadcastMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(Self::Both),
+            "channel" => Ok(Self::Channel),
+            _ => Err(format!("unknown broadcast mode {} (both or channel)", s)),
+        }
+    }
+}
+
+/// A forum tag of the channel config, given to posts whose subject, the first line of the
+/// Slack message, matches `pattern`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ForumTagRule {
+    pub name: String,
+    /// Regex, matched anywhere in the subject.
+    pub pattern: String,
+}
+
+/// A forum `post_channel` posts into, with the tags of its posts.
+#[derive(Clone, Debug, Default)]
+pub struct Forum {
+    /// Id of a tag of the forum, and the subjects that get it.
+    tags: Vec<(String, regex::Regex)>,
+}
+
+impl Forum {
+    /// Tags of `rules` by their ids in `available`, the tags the forum has. Rules naming a tag
+    /// the forum lacks, as it was created before the rule, are left out with a warning.
+    pub fn new(
+        rules: &[ForumTagRule],
+        available: &[discord::ForumTag],
+    ) -> Result<Self, anyhow::Error> {
+        let mut tags = Vec::new();
+        for rule in rules {
+            let pattern = regex::Regex::new(&rule.pattern)
+                .with_context(|| format!("pattern of forum tag {}", rule.name))?;
+            match available.iter().find(|tag| tag.name == rule.name) {
+                Some(tag) => tags.push((tag.id.clone(), pattern)),
+                None => warn!(
+                    "forum has no tag {}, add it on Discord to apply it",
+                    rule.name
+                ),
+            }
+        }
+        Ok(Self { tags })
+    }
+
+    /// The post started by a message with `subject`, named after it or `fallback` when it has
+    /// no text.
+    fn post(&self, subject: &str, fallback: &str) -> sink::ForumPost {
+        let name = match subject
+            .chars()
+            .take(discord::THREAD_NAME_MAX_CHARS)
+            .collect::<String>()
+        {
+            name if name.trim().is_empty() => fallback.to_owned(),
+            name => name,
+        };
+        let tags = self
+            .tags
+            .iter()
+            .filter(|(_, pattern)| pattern.is_match(subject))
+            .map(|(id, _)| id.clone())
+            .take(discord::FORUM_POST_MAX_TAGS)
+            .collect();
+        sink::ForumPost { name, tags }
+    }
+}
+
+/// First line of `text` with text, trimmed.
+fn subject_of(text: &str) -> &str {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+}
+
+/// How far `post_channel` got through the messages of a channel it is going to post, those
+/// left out by options not counted.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelProgress {
+    /// Messages posted, or found posted before, so far.
+    pub handled: usize,
+    pub total: usize,
+}
+
+/// How `post_channel` renders and posts one channel.
+#[derive(Clone)]
+pub struct PostOptions {
+    /// Prefix each message with `**user** date`.
+    pub include_header: bool,
+    pub broadcast: BroadcastMode,
+    /// Post thread replies into the channel after a reference to their parent instead of
+    /// starting threads.
+    pub inline_threads: bool,
+    /// Pin messages that were pinned in the Slack channel.
+    pub pin: bool,
+    /// Prefix messages starred by the exporting user with ⭐.
+    pub star_prefix: bool,
+    /// Replaces the `**user** date` header and text when set.
+    pub template: Option<template::MessageTemplate>,
+    /// Slack workspace for `{{permalink}}` in the template.
+    pub workspace_url: Option<String>,
+    /// Drop a message repeating the text of the previous kept one by the same user within this
+    /// window.
+    pub dedup_window: Option<Duration>,
+    /// Leave out the `**user** date` header of a message following one by the same user
+    /// within this window, in the channel or the same thread, like Discord groups messages.
+    pub group_window: Option<Duration>,
+    /// Times a post with attachments is sent again when it fails, before it is posted without
+    /// any of them.
+    pub file_retries: u32,
+    /// Append `💬 N replies (participants)` to thread parents.
+    pub thread_summary: bool,
+    /// How users are named in headers, mentions and references.
+    pub name_style: slack::NameStyle,
+    /// Threads of a channel posted at once. Channel messages, and each thread's replies, keep
+    /// their order.
+    pub workers_per_channel: usize,
+    /// strftime pattern for dates in headers and references, RFC 2822 when unset. Check it
+    /// with [`validate_date_format`] first, a bad pattern panics when formatted.
+    pub date_format: Option<String>,
+    /// Images over the attachment limit are posted as a thumbnail fitting this many pixels
+    /// on each side, along with a link to the original. They are skipped when unset.
+    pub image_downscale: Option<u32>,
+    /// Skip a reply whose thread was never posted, instead of failing the channel.
+    pub continue_on_error: bool,
+    /// Keep Discord from unfurling a message that is only a link, as Slack did already.
+    pub suppress_url_embeds: bool,
+    /// Append a `Reactions: 👍 x3, :custom: x1` line to messages with reactions.
+    pub reactions_as_text: bool,
+    /// No message is started after this, the channel ends early with
+    /// `ChannelReport::out_of_time` set.
+    pub deadline: Option<std::time::Instant>,
+    pub thread_pass: ThreadPass,
+    /// Render `blocks` instead of `text` whenever a message has them, not only when `text`
+    /// is empty.
+    pub prefer_blocks: bool,
+    /// Leave out the messages Slack posts itself, see [`slack::MessageSubType::is_system`].
+    pub skip_system_messages: bool,
+    pub file_pass: FilePass,
+    /// Gives up on the run after too many posts fail in a row, when set.
+    pub failure_budget: Option<Arc<FailureBudget>>,
+    /// Post into a forum: every top-level message starts a post, which its replies go to.
+    /// Replies shown in the channel stay in the post, as a forum has nowhere else to put them.
+    pub forum: Option<Forum>,
+    /// Called after every message, from thread workers too.
+    pub on_progress: Option<Arc<dyn Fn(ChannelProgress) + Send + Sync>>,
+}
+
+impl Default for PostOptions {
+    fn default() -> Self {
+        Self {
+            include_header: true,
+            broadcast: BroadcastMode::Both,
+            inline_threads: false,
+            pin: false,
+            star_prefix: false,
+            template: None,
+            workspace_url: None,
+            dedup_window: None,
+            group_window: None,
+            file_retries: 2,
+            thread_summary: false,
+            name_style: slack::NameStyle::RealName,
+            workers_per_channel: 1,
+            date_format: None,
+            image_downscale: None,
+            continue_on_error: false,
+            suppress_url_embeds: false,
+            reactions_as_text: false,
+            deadline: None,
+            thread_pass: ThreadPass::All,
+            prefer_blocks: false,
+            skip_system_messages: false,
+            file_pass: FilePass::All,
+            failure_budget: None,
+            forum: None,
+            on_progress: None,
+        }
+    }
+}
+
+impl PostOptions {
+    fn record_post<T>(&self, result: Result<T, anyhow::Error>) -> Result<T, anyhow::Error> {
+        match &self.failure_budget {
+            Some(budget) => budget.record(result),
+            None => result,
+        }
+    }
+
+    fn out_of_time(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+    }
+
+    fn format_date(&self, ts: &slack::TimeStamp) -> String {
+        match &self.date_format {
+            Some(format) => ts.jtc_date().format(format).to_string(),
+            None => ts.jtc_date().to_rfc2822(),
+        }
+    }
+}
+
+/// `Reactions: 👍 x3, :custom: x1` on its own line. Shortcodes are turned into the emoji they
+/// stand for, custom ones stay as they are.
+fn render_reactions(reactions: &[slack::Reaction]) -> String {
+    let reactions = reactions
+        .iter()
+        .map(|reaction| {
+            // skin tones come as a second shortcode, `+1::skin-tone-2`
+            let name = reaction.name.split("::").next().unwrap_or(&reaction.name);
+            match emojis::get_by_shortcode(name) {
+                Some(emoji) => format!("{} x{}", emoji.as_str(), reaction.count),
+                None => format!(":{}: x{}", name, reaction.count),
+            }
+        })
+        .join(", ");
+    format!("Reactions: {}\n", reactions)
+}
+
+/// Rename files sharing a name with an earlier one of the same message to `name (2).ext`
+/// and so on, so sinks keeping files by name get all of them.
+fn dedup_filenames(files: Vec<(String, discord::FilePost)>) -> Vec<(String, discord::FilePost)> {
+    let mut used = HashSet::new();
+    files
+        .into_iter()
+        .map(|(name, file)| {
+            let (stem, extension) = match name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+                _ => (name.as_str(), None),
+            };
+            let mut unique = name.clone();
+            let mut copy = 1;
+            while !used.insert(unique.clone()) {
+                copy += 1;
+                unique = match extension {
+                    Some(extension) => format!("{} ({}).{}", stem, copy, extension),
+                    None => format!("{} ({})", stem, copy),
+                };
+            }
+            (unique, file)
+        })
+        .collect()
+}
+
+/// Whether `downscale_image` takes files of the Slack `filetype`.
+fn is_image_filetype(filetype: &str) -> bool {
+    matches!(filetype, "png" | "jpg" | "jpeg" | "gif" | "webp")
+}
+
+/// Key of `filetype` in `ChannelReport::attached_bytes` and `oversized_bytes`.
+fn filetype_key(filetype: &Option<String>) -> String {
+    filetype.clone().unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Shrink the image `body` to fit `max_dimension` on each side. JPEGs stay JPEGs, anything
+/// else becomes a PNG named after `name`.
+fn downscale_image(
+    name: &str,
+    body: &[u8],
+    max_dimension: u32,
+) -> Result<(String, discord::FilePost), anyhow::Error> {
+    let format = image::guess_format(body).with_context(|| format!("guess format of {}", name))?;
+    let thumbnail = image::load_from_memory_with_format(body, format)
+        .with_context(|| format!("decode {}", name))?
+        .thumbnail(max_dimension, max_dimension);
+    let (format, mime, name) = if format == image::ImageFormat::Jpeg {
+        (format, "image/jpeg", name.to_owned())
+    } else {
+        let stem = std::path::Path::new(name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name);
+        (
+            image::ImageFormat::Png,
+            "image/png",
+            format!("{}.png", stem),
+        )
+    };
+    let mut out = std::io::Cursor::new(Vec::new());
+    thumbnail
+        .write_to(&mut out, format)
+        .with_context(|| format!("encode preview of {}", name))?;
+    Ok((
+        name,
+        discord::FilePost {
+            mime: mime.to_owned(),
+            title: String::new(),
+            body: out.into_inner(),
+        },
+    ))
+}
+
+/// Fail on a strftime pattern chrono cannot format.
+pub fn validate_date_format(format: &str) -> Result<(), anyhow::Error> {
+    if chrono::format::StrftimeItems::new(format)
+        .any(|item| matches!(item, chrono::format::Item::Error))
+    {
+        anyhow::bail!("invalid date format {:?}", format);
+    }
+    Ok(())
+}
+
+fn render_message(
+    options: &PostOptions,
+    user_id_to_real_name: &HashMap<String, String>,
+    user: &str,
+    ts: &slack::TimeStamp,
+    text: &str,
+    grouped: bool,
+) -> String {
+    let text = replace_slack_id_to_real_name(user_id_to_real_name, text);
+    if !options.include_header || grouped {
+        return format!("{}\n", text);
+    }
+    let user_name = user_id_to_real_name
+        .get(user)
+        .map(String::as_str)
+        .unwrap_or(user);
+    format!("**{}** {}\n{}\n", user_name, options.format_date(ts), text)
+}
+
+/// `> ↳ reply to **user** date` line naming the parent of an inlined reply.
+fn render_reply_reference(
+    options: &PostOptions,
+    user_id_to_real_name: &HashMap<String, String>,
+    messages: &[Message],
+    thread_ts: &slack::TimeStamp,
+) -> String {
+    let parent = messages
+        .binary_search_by(|message| message.ts().cmp(thread_ts))
+        .ok()
+        .map(|index| {
+            let user = messages[index].author();
+            user_id_to_real_name
+                .get(user)
+                .map(String::as_str)
+                .unwrap_or(user)
+        });
+    match parent {
+        Some(user) => format!(
+            "> ↳ reply to **{}** {}\n",
+            user,
+            options.format_date(thread_ts)
+        ),
+        None => format!("> ↳ reply to {}\n", options.format_date(thread_ts)),
+    }
+}
+
+/// `💬 N replies (user, ...)` line for a thread parent, naming repliers in the order they first
+/// replied. Participants are left out, then the whole line, when `text` would not fit in a
+/// message with it.
+fn render_thread_summary(
+    user_id_to_real_name: &HashMap<String, String>,
+    messages: &[Message],
+    ts: &slack::TimeStamp,
+    reply_count: u64,
+    text: &str,
+) -> String {
+    let participants = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Message {
+                ts: reply_ts,
+                thread_ts: Some(thread_ts),
+                ..
+            } if thread_ts == ts && reply_ts != ts => {
+                let user = message.author();
+                Some(
+                    user_id_to_real_name
+                        .get(user)
+                        .map(String::as_str)
+                        .unwrap_or(user),
+                )
+            }
+            _ => None,
+        })
+        .unique()
+        .join(", ");
+    let count = if reply_count == 1 {
+        "💬 1 reply".to_owned()
+    } else {
+        format!("💬 {} replies", reply_count)
+    };
+    let room = MESSAGE_CONTENT_MAX_CHARS.saturating_sub(text.chars().count());
+    [
+        format!("{} ({})\n", count, participants),
+        format!("{}\n", count),
+    ]
+    .into_iter()
+    .skip(if participants.is_empty() { 1 } else { 0 })
+    .find(|summary| summary.chars().count() <= room)
+    .unwrap_or_default()
+}
+
+fn render_attachments(
+    user_id_to_real_name: &HashMap<String, String>,
+    attachments: &[slack::Attachment],
+) -> String {
+    let mut out = String::new();
+    for attachment in attachments {
+        let mut lines = Vec::new();
+        if let Some(author_name) = &attachment.author_name {
+            lines.push(author_name.clone());
+        }
+        match (&attachment.title, &attachment.title_link) {
+            (Some(title), Some(link)) => lines.push(format!("**[{}]({})**", title, link)),
+            (Some(title), None) => lines.push(format!("**{}**", title)),
+            _ => (),
+        }
+        if let Some(text) = &attachment.text {
+            lines.extend(text.lines().map(str::to_owned));
+        }
+        for field in attachment.fields.iter().flatten() {
+            lines.push(format!("**{}**: {}", field.title, field.value));
+        }
+        if lines.is_empty() {
+            if let Some(fallback) = &attachment.fallback {
+                lines.push(fallback.clone());
+            }
+        }
+
+        if let Some(pretext) = &attachment.pretext {
+            out.push_str(pretext);
+            out.push('\n');
+        }
+        for line in lines {
+            out.push_str("> ");
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    replace_slack_id_to_real_name(user_id_to_real_name, &out)
+}
+
+/// User ids mentioned in `channels` that are missing from `users`, with how often each is
+/// mentioned. These are posted as the raw `<@U...>` token.
+pub fn unresolved_mentions(
+    channels: &[SlackChannel],
+    users: &HashMap<String, slack::User>,
+) -> BTreeMap<String, usize> {
+    let mut unresolved = BTreeMap::new();
+    for message in channels.iter().flat_map(|channel| &channel.messages) {
+        let Message::Message {
+            text, attachments, ..
+        } = message;
+        let attachments = attachments
+            .as_deref()
+            .map(|attachments| render_attachments(&HashMap::new(), attachments))
+            .unwrap_or_default();
+        for text in [text.as_str(), attachments.as_str()] {
+            for caps in SLACK_USER_MENTION.captures_iter(text) {
+                if !users.contains_key(&caps[1]) {
+                    *unresolved.entry(caps[1].to_owned()).or_default() += 1;
+                }
+            }
+        }
+    }
+    unresolved
+}
+
+/// A file left out of its message. Files go up together with their message, so when the upload
+/// keeps failing all the files of the message are given up, not only the one Discord refused.
+#[derive(Debug)]
+pub struct FailedFile {
+    pub ts: slack::TimeStamp,
+    pub title: String,
+    pub url: String,
+    pub error: anyhow::Error,
+}
+
+#[derive(Debug, Default)]
+pub struct PrefetchReport {
+    /// Files already in the cache.
+    pub cached: usize,
+    pub downloaded: usize,
+    pub failed_files: Vec<FailedFile>,
+}
+
+/// Download every hosted file of `channels` into the `files` cache, `concurrency` at a time,
+/// so a migration after it needs no file downloads.
+pub async fn prefetch_files(
+    db: &Db,
+    channels: &[SlackChannel],
+    concurrency: usize,
+) -> PrefetchReport {
+    let files = channels
+        .iter()
+        .flat_map(|channel| &channel.messages)
+        .flat_map(|Message::Message { ts, files, .. }| {
+            files.iter().flatten().filter_map(move |file| match file {
+                slack::File::Hosted {
+                    title,
+                    url_private_download: Some(url_private_download),
+                    ..
+                } => Some((ts, title, url_private_download)),
+                _ => None,
+            })
+        })
+        .unique_by(|(_, _, url)| *url)
+        .collect::<Vec<_>>();
+    info!("prefetch {} files", files.len());
+    let results = futures::stream::iter(files)
+        .map(|(ts, title, url)| async move {
+            let cached = db.is_cached(url).await.map_err(anyhow::Error::from);
+            let result = match cached {
+                Ok(true) => Ok(true),
+                Ok(false) => db.fetch_file(url).await.map(|_| false).map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            result.map_err(|error| FailedFile {
+                ts: ts.clone(),
+                title: title.clone(),
+                url: url.clone(),
+                error,
+            })
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let mut report = PrefetchReport::default();
+    for result in results {
+        match result {
+            Ok(true) => report.cached += 1,
+            Ok(false) => report.downloaded += 1,
+            Err(failed) => {
+                warn!("download {} failed: {:#}", failed.url, failed.error);
+                report.failed_files.push(failed);
+            }
+        }
+    }
+    report
+}
+
+#[derive(Debug, Default)]
+pub struct ChannelReport {
+    pub failed_files: Vec<FailedFile>,
+    /// Messages dropped as repeats by `PostOptions::dedup_window`.
+    pub collapsed: usize,
+    /// Messages posted by this run, not counting ones already recorded as posted.
+    pub posted: usize,
+    /// Replies left out under `PostOptions::continue_on_error`, as their thread was not posted.
+    pub skipped_replies: Vec<slack::TimeStamp>,
+    /// `PostOptions::deadline` passed before every message was posted.
+    pub out_of_time: bool,
+    /// Messages Discord timestamped before the message posted ahead of them.
+    pub out_of_order: Vec<slack::TimeStamp>,
+    /// Bytes of files attached, by Slack filetype.
+    pub attached_bytes: BTreeMap<String, u64>,
+    /// Bytes of files left out without downloading them, as Slack listed them over the
+    /// attachment limit, by Slack filetype.
+    pub oversized_bytes: BTreeMap<String, u64>,
+    /// Timestamp of the latest post, to compare the next one with.
+    last_timestamp: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+/// Post `message` with `files`, downloaded from `urls`, as the start of `forum_post` if given.
+/// A post that keeps failing is sent without its attachments and a note instead, so one bad
+/// file does not stop the channel.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(%ts, operation = "post"))]
+async fn post_with_files<S: MessageSink + Sync>(
+    sink: &S,
+    channel: &str,
+    forum_post: Option<&sink::ForumPost>,
+    message: &discord::MessagePost,
+    files: Vec<(String, discord::FilePost)>,
+    urls: &[String],
+    ts: &slack::TimeStamp,
+    retries: u32,
+    report: &mut ChannelReport,
+) -> Result<PostedParts, anyhow::Error> {
+    let mut parts = split_content(&message.content);
+    let last = parts.pop().unwrap_or_default();
+    let mut ids = Vec::new();
+    // the first part starts the forum post, the others follow it in its thread
+    let mut thread = None;
+    let result = async {
+        for part in parts {
+            let part = discord::MessagePost {
+                content: part,
+                flags: message.flags,
+            };
+            let posted = post_part(sink, channel, forum_post, thread.as_deref(), &part).await?;
+            check_order(report, ts, &posted);
+            thread = thread.take().or(posted.thread);
+            ids.push(posted.id);
+        }
+        // the files go with the last part, after all of the text
+        let last = discord::MessagePost {
+            content: last,
+            flags: message.flags,
+        };
+        let attached = post_with_files_or_notes(
+            sink,
+            thread.as_deref().unwrap_or(channel),
+            forum_post.filter(|_| thread.is_none()),
+            &last,
+            files,
+            urls,
+            ts,
+            retries,
+            report,
+        )
+        .await?;
+        let parts = match attached {
+            Attached::Posted(posted) => {
+                check_order(report, ts, &posted);
+                thread = thread.take().or(posted.thread);
+                ids.push(posted.id);
+                Vec::new()
+            }
+            // the notes can take the last part over the limit, so it is split again
+            Attached::Refused { notes } => split_content(&(last.content + &notes)),
+        };
+        for part in parts {
+            let part = discord::MessagePost {
+                content: part,
+                flags: message.flags,
+            };
+            let posted = post_part(sink, channel, forum_post, thread.as_deref(), &part).await?;
+            check_order(report, ts, &posted);
+            thread = thread.take().or(posted.thread);
+            ids.push(posted.id);
+        }
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    if let Err(e) = result {
+        discard_parts(sink, channel, &ids, thread.as_deref()).await;
+        return Err(e);
+    }
+    let id = ids.remove(0);
+    Ok(PostedParts {
+        id,
+        rest: ids,
+        thread,
+    })
+}
+
+/// Post a part of a message without files, as the start of `forum_post` if no earlier part
+/// started its `thread`, into the thread if one did.
+async fn post_part<S: MessageSink + Sync>(
+    sink: &S,
+    channel: &str,
+    forum_post: Option<&sink::ForumPost>,
+    thread: Option<&str>,
+    part: &discord::MessagePost,
+) -> Result<sink::Posted, anyhow::Error> {
+    match (forum_post, thread) {
+        (Some(forum_post), None) => {
+            sink.post_forum_thread(channel, forum_post, part, Vec::new())
+                .await
+        }
+        (_, thread) => sink.post(thread.unwrap_or(channel), part, Vec::new()).await,
+    }
+}
+
+/// Delete the parts of a message that were posted before a later part failed, so nothing
+/// unrecorded is left behind. A forum post the parts started goes with all of them.
+async fn discard_parts<S: MessageSink + Sync>(
+    sink: &S,
+    channel: &str,
+    ids: &[String],
+    thread: Option<&str>,
+) {
+    let result = match thread {
+        Some(thread) => sink.delete_thread(thread).await,
+        None => {
+            let mut result = Ok(());
+            for id in ids {
+                result = result.and(sink.delete(channel, id).await);
+            }
+            result
+        }
+    };
+    if let Err(e) = result {
+        warn!(
+            "failed to delete the {} parts posted ahead of the failed one: {:#}",
+            ids.len(),
+            e
+        );
+    }
+}
+
+/// Messages of one report are posted one after the other, so their timestamps only grow.
+fn check_order(report: &mut ChannelReport, ts: &slack::TimeStamp, posted: &sink::Posted) {
+    if let Some(timestamp) = posted.timestamp {
+        if report.last_timestamp.is_some_and(|last| timestamp < last) {
+            warn!("{} landed before the message posted ahead of it", ts);
+            report.out_of_order.push(ts.clone());
+        }
+        report.last_timestamp = Some(timestamp);
+    }
+}
+
+/// Discord messages one Slack message was posted as, more than one when its content was over
+/// Discord's limit. `id` is the first part, the one `posts` records and threads start on.
+#[derive(Debug, Clone)]
+struct PostedParts {
+    id: String,
+    rest: Vec<String>,
+    /// Thread of the forum post the message started.
+    thread: Option<String>,
+}
+
+/// Record the parts of `posted` after the first, in `discord_channel_id`, so a rollback
+/// deletes them along with it.
+async fn record_parts(
+    db: &Db,
+    posted: &PostedParts,
+    discord_channel_id: &str,
+) -> Result<(), sqlx::Error> {
+    for part in &posted.rest {
+        sqlx::query!(
+            "insert or ignore into post_parts values (?, ?, ?);",
+            part,
+            posted.id,
+            discord_channel_id
+        )
+        .execute(&db.pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Split `content` into parts within Discord's content limit, at line ends where there are
+/// any. Content within the limit is the only part.
+fn split_content(content: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = content;
+    while rest.chars().count() > MESSAGE_CONTENT_MAX_CHARS {
+        let limit = rest
+            .char_indices()
+            .nth(MESSAGE_CONTENT_MAX_CHARS)
+            .map(|(index, _)| index)
+            .unwrap_or(rest.len());
+        let end = match rest[..limit].rfind('\n') {
+            Some(newline) if newline > 0 => newline + 1,
+            _ => limit,
+        };
+        parts.push(rest[..end].to_owned());
+        rest = &rest[end..];
+    }
+    parts.push(rest.to_owned());
+    parts
+}
+
+/// How the part of a message carrying its files went.
+enum Attached {
+    Posted(sink::Posted),
+    /// Discord kept refusing the files, so the part is left to be posted without them and
+    /// `notes` on what was left out.
+    Refused {
+        notes: String,
+    },
+}
+
+/// Post `message` with `files`, retrying `retries` times before giving up on the files.
+#[allow(clippy::too_many_arguments)]
+async fn post_with_files_or_notes<S: MessageSink + Sync>(
+    sink: &S,
+    channel: &str,
+    forum_post: Option<&sink::ForumPost>,
+    message: &discord::MessagePost,
+    files: Vec<(String, discord::FilePost)>,
+    urls: &[String],
+    ts: &slack::TimeStamp,
+    retries: u32,
+    report: &mut ChannelReport,
+) -> Result<Attached, anyhow::Error> {
+    let post = |message: &discord::MessagePost, files| {
+        let message = message.clone();
+        async move {
+            match forum_post {
+                Some(forum_post) => {
+                    sink.post_forum_thread(channel, forum_post, &message, files)
+                        .await
+                }
+                None => sink.post(channel, &message, files).await,
+            }
+        }
+    };
+    if files.is_empty() {
+        return post(message, files).await.map(Attached::Posted);
+    }
+    let mut attempt = 0;
+    let error = loop {
+        match post(message, files.clone()).await {
+            Ok(posted) => return Ok(Attached::Posted(posted)),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!("post {} with files failed, retry {}: {:#}", ts, attempt, e);
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            }
+            Err(e) => break e,
+        }
+    };
+    warn!("post {} without files: {:#}", ts, error);
+    let mut notes = String::new();
+    for ((_, file), url) in files.iter().zip(urls) {
+        notes.push_str(&format!("(failed to attach {})\n", file.title));
+        report.failed_files.push(FailedFile {
+            ts: ts.clone(),
+            title: file.title.clone(),
+            url: url.clone(),
+            error: anyhow::anyhow!("upload failed: {:#}", error),
+        });
+    }
+    Ok(Attached::Refused { notes })
+}
+
+/// Start the thread of an already recorded post and record the thread.
+#[tracing::instrument(skip_all, fields(post = %message, operation = "start_thread"))]
+async fn start_thread<S: MessageSink + Sync>(
+    db: &Db,
+    sink: &S,
+    channel: &str,
+    message: &str,
+) -> Result<String, anyhow::Error> {
+    let thread_id = sink.start_thread(channel, message, "slack thread").await?;
+    sqlx::query!(
+        "update posts set discord_thread_id = ? where id = ?",
+        thread_id,
+        message
+    )
+    .execute(&db.pool)
+    .await
+    .with_context(|| format!("record thread of msg.id: {}", message))?;
+    Ok(thread_id)
+}
+
+#[derive(Debug, Default)]
+pub struct RollbackReport {
+    /// Threads deleted along with their replies.
+    pub threads: usize,
+    pub messages: usize,
+    /// Messages already gone from Discord.
+    pub missing: usize,
+}
+
+/// Delete the Discord messages recorded in `posts`, of one Slack channel or of all, and the
+/// rows with them. Rows are dropped as their messages go, so an interrupted rollback resumes.
+pub async fn rollback(
+    db: &Db,
+    client: &discord::DiscordClient,
+    slack_channel_id: Option<&str>,
+) -> Result<RollbackReport, anyhow::Error> {
+    let rows = match slack_channel_id {
+        Some(slack_channel_id) => {
+            sqlx::query_as!(
+                PostRecord,
+                "select * from posts where slack_channel_id = ?",
+                slack_channel_id
+            )
+            .fetch_all(&db.pool)
+            .await
+        }
+        None => {
+            sqlx::query_as!(PostRecord, "select * from posts")
+                .fetch_all(&db.pool)
+                .await
+        }
+    }
+    .with_context(|| "read posts")?;
+    // later parts of split posts, as (id, discord channel)
+    let parts = sqlx::query!(
+        r#"select post_parts.id as "id!", post_parts.discord_channel_id as "discord_channel_id!"
+        from post_parts join posts on posts.id = post_parts.post_id
+        where ?1 is null or posts.slack_channel_id = ?1"#,
+        slack_channel_id
+    )
+    .fetch_all(&db.pool)
+    .await
+    .with_context(|| "read post parts")?
+    .into_iter()
+    .map(|part| (part.id, part.discord_channel_id))
+    .collect::<HashSet<_>>();
+    let mut report = RollbackReport::default();
+
+    // a thread started on a message shares its id, and deleting it deletes the replies
+    let threads = rows
+        .iter()
+        .filter(|row| row.discord_thread_id.as_deref() == Some(row.id.as_str()))
+        .map(|row| row.id.clone())
+        .collect::<HashSet<_>>();
+    for thread in &threads {
+        match client.delete_channel(&thread.clone().into()).await {
+            Ok(()) => report.threads += 1,
+            Err(e) if e.is_not_found() => report.missing += 1,
+            Err(e) => return Err(e).with_context(|| format!("delete thread {}", thread)),
+        }
+        sqlx::query!(
+            "delete from posts where discord_thread_id = ? and id != ?",
+            thread,
+            thread
+        )
+        .execute(&db.pool)
+        .await?;
+        sqlx::query!(
+            "delete from post_parts where discord_channel_id = ?",
+            thread
+        )
+        .execute(&db.pool)
+        .await?;
+    }
+
+    let mut by_channel: HashMap<&str, Vec<discord::MessageId>> = HashMap::new();
+    // parts go first, their rows are only found through the post they belong to
+    for (id, channel) in &parts {
+        if !threads.contains(channel) {
+            by_channel
+                .entry(channel)
+                .or_default()
+                .push(id.clone().into());
+        }
+    }
+    for row in &rows {
+        let location = match &row.discord_thread_id {
+            Some(thread) if *thread != row.id => thread,
+            _ => &row.discord_channel_id,
+        };
+        if !threads.contains(location) {
+            by_channel
+                .entry(location)
+                .or_default()
+                .push(row.id.clone().into());
+        }
+    }
+    let now = std::time::SystemTime::now();
+    // an hour of slack, so messages don't age out between listing and deleting
+    let bulk_max_age = discord::BULK_DELETE_MAX_AGE - Duration::from_secs(60 * 60);
+    for (channel, messages) in by_channel {
+        let channel_id = discord::ChannelId::from(channel.to_owned());
+        let (recent, mut single): (Vec<_>, Vec<_>) = messages.into_iter().partition(|message| {
+            message
+                .created_at()
+                .and_then(|created_at| now.duration_since(created_at).ok())
+                .is_some_and(|age| age < bulk_max_age)
+        });
+        for chunk in recent.chunks(discord::BULK_DELETE_MAX) {
+            if chunk.len() < 2 {
+                single.extend_from_slice(chunk);
+                continue;
+            }
+            client
+                .bulk_delete_messages(&channel_id, chunk)
+                .await
+                .with_context(|| format!("bulk delete in {}", channel))?;
+            report.messages += chunk.len();
+            for message in chunk {
+                let message = message.to_string();
+                sqlx::query!("delete from posts where id = ?", message)
+                    .execute(&db.pool)
+                    .await?;
+                sqlx::query!("delete from post_parts where id = ?", message)
+                    .execute(&db.pool)
+                    .await?;
+            }
+            info!("deleted {} messages in {}", chunk.len(), channel);
+        }
+        for message in single {
+            if message.created_at().is_none() {
+                warn!(
+                    "{} is not a Discord message, only its record is dropped",
+                    message
+                );
+            } else {
+                match client.delete_message(&channel_id, &message).await {
+                    Ok(()) => report.messages += 1,
+                    Err(e) if e.is_not_found() => report.missing += 1,
+                    Err(e) => return Err(e).with_context(|| format!("delete {}", message)),
+                }
+            }
+            let message = message.to_string();
+            sqlx::query!("delete from posts where id = ?", message)
+                .execute(&db.pool)
+                .await?;
+            sqlx::query!("delete from post_parts where id = ?", message)
+                .execute(&db.pool)
+                .await?;
+        }
+    }
+    Ok(report)
+}
+
+/// Per-channel state shared by every message of [`post_channel`], also across thread workers.
+struct ChannelPoster<'a, S> {
+    db: &'a Db,
+    sink: &'a S,
+    destination: &'a str,
+    channel: &'a SlackChannel,
+    users: &'a HashMap<String, slack::User>,
+    options: &'a PostOptions,
+    user_id_to_real_name: HashMap<String, String>,
+    attachment_limit: usize,
+    reply_counts: HashMap<&'a slack::TimeStamp, u64>,
+    /// Messages posted without a header, see `PostOptions::group_window`.
+    grouped: HashSet<&'a slack::TimeStamp>,
+}
+
+impl<'a, S: MessageSink + Sync> ChannelPoster<'a, S> {
+    /// Download the hosted `files` of the message `ts` for posting, with the URLs they came
+    /// from. Notes on previews and failed files are appended to `text`.
+    async fn fetch_attachments(
+        &self,
+        ts: &slack::TimeStamp,
+        files: &Option<Vec<slack::File>>,
+        text: &mut String,
+        report: &mut ChannelReport,
+    ) -> (Vec<(String, discord::FilePost)>, Vec<String>) {
+        let db = self.db;
+        let options = self.options;
+        let attachment_limit = self.attachment_limit;
+
+        // images over the limit may still fit once downscaled, and need downloading for it
+        let (files, oversized): (Vec<_>, Vec<_>) =
+            files.iter().flatten().partition(|file| match file {
+                slack::File::Hosted {
+                    size: Some(size),
+                    filetype,
+                    ..
+                } => {
+                    *size <= attachment_limit as u64
+                        || (options.image_downscale.is_some()
+                            && filetype.as_deref().is_none_or(is_image_filetype))
+                }
+                _ => true,
+            });
+        for file in oversized {
+            if let slack::File::Hosted {
+                title,
+                size: Some(size),
+                filetype,
+                ..
+            } = file
+            {
+                info!(
+                    "skip file {} of {} MiB, over the attachment limit",
+                    title,
+                    *size as f64 / 1024.0 / 1024.0
+                );
+                *report
+                    .oversized_bytes
+                    .entry(filetype_key(filetype))
+                    .or_default() += size;
+            }
+        }
+        let (files, failed_files): (Vec<_>, Vec<_>) = futures::stream::iter(files)
+            .filter_map(|file| async move {
+                match file {
+                    slack::File::Hosted {
+                        name,
+                        title,
+                        url_private_download: Some(url_private_download),
+                        filetype,
+                        ..
+                    } => match db.fetch_file(url_private_download).await {
+                        Ok(file_raw) => {
+                            info!(
+                                "file {} size {} MiB",
+                                url_private_download,
+                                file_raw.inner.len() as f64 / 1024.0 / 1024.0
+                            );
+                            if file_raw.inner.len() <= attachment_limit {
+                                let file = discord::FilePost {
+                                    mime: file_raw.mime.clone(),
+                                    title: title.clone(),
+                                    body: file_raw.inner,
+                                };
+                                return Some(Ok((
+                                    url_private_download.clone(),
+                                    (name.clone(), file),
+                                    None,
+                                    filetype,
+                                )));
+                            }
+                            let max_dimension = match options.image_downscale {
+                                Some(max_dimension) if file_raw.mime.starts_with("image/") => {
+                                    max_dimension
+                                }
+                                _ => {
+                                    warn!(
+                                        "{} is over the attachment limit, left out",
+                                        url_private_download
+                                    );
+                                    return None;
+                                }
+                            };
+                            match downscale_image(name, &file_raw.inner, max_dimension) {
+                                Ok((name, file)) if file.body.len() <= attachment_limit => {
+                                    let note = format!(
+                                        "(preview of {}, full size at {})\n",
+                                        title,
+                                        slack::strip_token(url_private_download)
+                                    );
+                                    let file = discord::FilePost {
+                                        title: title.clone(),
+                                        ..file
+                                    };
+                                    Some(Ok((
+                                        url_private_download.clone(),
+                                        (name, file),
+                                        Some(note),
+                                        filetype,
+                                    )))
+                                }
+                                Ok(_) => {
+                                    warn!(
+                                        "preview of {} is still over the attachment limit, left out",
+                                        url_private_download
+                                    );
+                                    None
+                                }
+                                Err(e) => {
+                                    warn!(
+                                        "downscale {} failed, left out: {:#}",
+                                        url_private_download, e
+                                    );
+                                    None
+                                }
+                            }
+                        }
+                        Err(error) => {
+                            warn!("download {} failed: {}", url_private_download, error);
+                            Some(Err(FailedFile {
+                                ts: ts.clone(),
+                                title: title.clone(),
+                                url: url_private_download.clone(),
+                                error: error.into(),
+                            }))
+                        }
+                    },
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .partition_result();
+        let mut urls = Vec::new();
+        let files = dedup_filenames(
+            files
+                .into_iter()
+                .map(|(url, file, note, filetype)| {
+                    urls.push(url);
+                    text.extend(note);
+                    *report
+                        .attached_bytes
+                        .entry(filetype_key(filetype))
+                        .or_default() += file.1.body.len() as u64;
+                    file
+                })
+                .collect(),
+        );
+        for failed in &failed_files {
+            text.push_str(&format!("(failed to attach {})\n", failed.title));
+        }
+        report.failed_files.extend(failed_files);
+        (files, urls)
+    }
+
+    /// Post the files a `SkipFiles` run left out of the already posted message `ts`, in a
+    /// follow-up next to it.
+    async fn post_pending_files(
+        &self,
+        ts: &slack::TimeStamp,
+        user: &str,
+        files: &Option<Vec<slack::File>>,
+        reply_count: &Option<u64>,
+        posted: &PostRecord,
+        report: &mut ChannelReport,
+    ) -> Result<(), anyhow::Error> {
+        let db = self.db;
+        let channel_id = &self.channel.id;
+        let pending = sqlx::query!(
+            "select slack_ts from pending_files where slack_channel_id = ? and slack_ts = ?",
+            channel_id,
+            ts
+        )
+        .fetch_optional(&db.pool)
+        .await?;
+        if pending.is_none() {
+            return Ok(());
+        }
+        let user_name = self
+            .user_id_to_real_name
+            .get(user)
+            .map(String::as_str)
+            .unwrap_or(user);
+        let mut text = format!(
+            "↳ files of **{}** {}\n",
+            user_name,
+            self.options.format_date(ts)
+        );
+        let (files, urls) = self.fetch_attachments(ts, files, &mut text, report).await;
+        // replies go back to their thread, anything else next to the message
+        let target = match (&posted.discord_thread_id, reply_count) {
+            (Some(thread_id), None) => thread_id,
+            _ => &posted.discord_channel_id,
+        };
+        let message = discord::MessagePost {
+            content: text,
+            flags: 0,
+        };
+        post_with_files(
+            self.sink,
+            target,
+            None,
+            &message,
+            files,
+            &urls,
+            ts,
+            self.options.file_retries,
+            report,
+        )
+        .await?;
+        sqlx::query!(
+            "delete from pending_files where slack_channel_id = ? and slack_ts = ?",
+            channel_id,
+            ts
+        )
+        .execute(&db.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn post_message(
+        &self,
+        message: &'a Message,
+        report: &mut ChannelReport,
+    ) -> Result<(), anyhow::Error> {
+        let db = self.db;
+        let sink = self.sink;
+        let destination = self.destination;
+        let channel = self.channel;
+        let users = self.users;
+        let options = self.options;
+        let user_id_to_real_name = &self.user_id_to_real_name;
+        let reply_counts = &self.reply_counts;
+        let Message::Message {
+            text,
+            files,
+            ts,
+            reply_count,
+            thread_ts,
+            attachments,
+            subtype,
+            is_starred,
+            pinned_to,
+            reactions,
+            blocks,
+            ..
+        } = message;
+        let rendered_blocks;
+        let text = if options.prefer_blocks || text.trim().is_empty() {
+            rendered_blocks = blocks::render_blocks(blocks);
+            if rendered_blocks.is_empty() {
+                text
+            } else {
+                &rendered_blocks
+            }
+        } else {
+            text
+        };
+        let user = message.author();
+        let broadcast = matches!(subtype, Some(slack::MessageSubType::ThreadBroadcast))
+            && options.forum.is_none();
+        // a broadcast posted in both places has a row for each copy
+        let message_on_db: Vec<PostRecord> = sqlx::query_as!(
+            PostRecord,
+            "select * from posts where slack_ts = ? and slack_channel_id = ?",
+            ts,
+            channel.id
+        )
+        .fetch_all(&db.pool)
+        .await
+        .with_context(|| format!("ts: {}, channel_id: {}", ts, channel.id))?;
+        let both_copies = broadcast
+            && options.broadcast == BroadcastMode::Both
+            && !options.inline_threads
+            && thread_ts.is_some()
+            && reply_count.is_none();
+        let in_thread = message_on_db.iter().any(PostRecord::is_in_thread);
+        let in_channel = message_on_db.iter().any(|posted| !posted.is_in_thread());
+        // a run stopped between the two copies of a broadcast posts the missing one
+        let missing_copy = both_copies && !message_on_db.is_empty() && !(in_thread && in_channel);
+        if missing_copy {
+            info!(
+                "post the missing {} copy of broadcast {}",
+                if in_thread { "channel" } else { "thread" },
+                ts
+            );
+        }
+        if message_on_db.is_empty() || missing_copy {
+            let lone_link = SLACK_LONE_LINK.is_match(text);
+            let forum_post = options.forum.as_ref().map(|forum| {
+                let text = replace_slack_id_to_real_name(user_id_to_real_name, text);
+                forum.post(subject_of(&text), &options.format_date(ts))
+            });
+            let mut text = match &options.template {
+                Some(template) => template.render(&template::TemplateContext {
+                    users,
+                    user,
+                    ts,
+                    text: &replace_slack_id_to_real_name(user_id_to_real_name, text),
+                    channel_id: &channel.id,
+                    workspace_url: options.workspace_url.as_deref(),
+                    files: &files.iter().flatten().collect::<Vec<_>>(),
+                }),
+                None => render_message(
+                    options,
+                    user_id_to_real_name,
+                    user,
+                    ts,
+                    text,
+                    self.grouped.contains(ts),
+                ),
+            };
+            if options.star_prefix && *is_starred {
+                text.insert_str(0, "⭐ ");
+            }
+            let pinned = options.pin && pinned_to.contains(&channel.id);
+            if options.reactions_as_text && !reactions.is_empty() {
+                text.push_str(&render_reactions(reactions));
+            }
+            if let Some(attachments) = attachments {
+                text.push_str(&render_attachments(user_id_to_real_name, attachments));
+            }
+            text.push_str(&render_file_links(files));
+            let pending_files = options.file_pass == FilePass::SkipFiles && has_hosted_files(files);
+            let (files, urls) = if options.file_pass == FilePass::SkipFiles {
+                (Vec::new(), Vec::new())
+            } else {
+                self.fetch_attachments(ts, files, &mut text, report).await
+            };
+            let thread_ts = match thread_ts {
+                Some(thread_ts) if options.inline_threads && reply_count.is_none() => {
+                    text.insert_str(
+                        0,
+                        &render_reply_reference(
+                            options,
+                            user_id_to_real_name,
+                            &channel.messages,
+                            thread_ts,
+                        ),
+                    );
+                    None
                 }
-                let message_on_db: Option<PostRecord> = sqlx::query_as!(
+                Some(_) if broadcast && options.broadcast == BroadcastMode::Channel => {
+                    text.push_str("(also in thread)\n");
+                    None
+                }
+                _ => thread_ts.as_ref(),
+            };
+            if let (Some(count), true) = (
+                reply_count.filter(|count| *count > 0),
+                options.thread_summary,
+            ) {
+                let summary = render_thread_summary(
+                    user_id_to_real_name,
+                    &channel.messages,
+                    ts,
+                    count,
+                    &text,
+                );
+                text.push_str(&summary);
+            }
+            let flags = if options.suppress_url_embeds && lone_link {
+                discord::MESSAGE_FLAG_SUPPRESS_EMBEDS
+            } else {
+                0
+            };
+            let message = discord::MessagePost {
+                content: text,
+                flags,
+            };
+            if let (Some(thread_ts), None) = (thread_ts, reply_count) {
+                debug!("reply to {}", thread_ts);
+                let thread = sqlx::query_as!(
                     PostRecord,
                     "select * from posts where slack_ts = ? and slack_channel_id = ?",
-                    ts,
+                    thread_ts,
                     channel.id
                 )
                 .fetch_optional(&db.pool)
-                .with_context(|| format!("ts: {}, channel_id: {}", ts, channel.id))
+                .await
+                .with_context(|| format!("ts: {}, channel_id: {}", ts, channel.id))?;
+                let thread = match thread {
+                    Some(thread) => thread,
+                    None if options.continue_on_error => {
+                        warn!(
+                            "skip reply {} in {}, thread {} was never posted",
+                            ts, channel.name, thread_ts
+                        );
+                        report.skipped_replies.push(ts.clone());
+                        return Ok(());
+                    }
+                    None => {
+                        return Err(PostError::ThreadParentMissing {
+                            slack_ts: thread_ts.clone(),
+                            channel: channel.name.clone(),
+                        }
+                        .into())
+                    }
+                };
+                let thread_id = thread.discord_thread_id.with_context(|| {
+                    format!("thread {} on {} not found", thread.slack_ts, channel.name)
+                })?;
+                let (files, broadcast_files) = if broadcast {
+                    (files.clone(), Some(files))
+                } else {
+                    (files, None)
+                };
+                if !in_thread {
+                    let posted = post_with_files(
+                        sink,
+                        &thread_id,
+                        None,
+                        &message,
+                        files,
+                        &urls,
+                        ts,
+                        options.file_retries,
+                        report,
+                    )
+                    .await?;
+                    let msg_id = &posted.id;
+
+                    // the thread is kept so a resumed run can tell where the reply went
+                    sqlx::query!(
+                        "insert into posts values (?, ?, ?, ?, ?);",
+                        msg_id,
+                        channel.id,
+                        destination,
+                        ts,
+                        thread_id,
+                    )
+                    .execute(&db.pool)
+                    .await?;
+                    record_parts(db, &posted, &thread_id).await?;
+                    if pinned {
+                        sink.pin(&thread_id, msg_id).await?;
+                    }
+
+                    if let Some(reply_count) = reply_counts.get(thread_ts) {
+                        sink.finish_thread(&thread_id, *reply_count).await?;
+                    }
+                }
+                if let Some(files) = broadcast_files.filter(|_| !in_channel) {
+                    let posted = post_with_files(
+                        sink,
+                        destination,
+                        None,
+                        &message,
+                        files,
+                        &urls,
+                        ts,
+                        options.file_retries,
+                        report,
+                    )
+                    .await?;
+                    let msg_id = &posted.id;
+                    sqlx::query!(
+                        "insert into posts values (?, ?, ?, ?, ?);",
+                        msg_id,
+                        channel.id,
+                        destination,
+                        ts,
+                        None::<String>,
+                    )
+                    .execute(&db.pool)
+                    .await?;
+                    record_parts(db, &posted, destination).await?;
+                }
+            } else {
+                let posted = post_with_files(
+                    sink,
+                    destination,
+                    forum_post.as_ref(),
+                    &message,
+                    files,
+                    &urls,
+                    ts,
+                    options.file_retries,
+                    report,
+                )
                 .await?;
-                if message_on_db.is_none() {
-                    let text = format!("**{}** {}\n{}\n", user, ts.jtc_date().to_rfc2822(), text);
-                    let message = discord::MessagePost {
-                        content: replace_slack_id_to_real_name(&user_id_to_real_name, &text),
-                    };
-                    let files = files.iter().flatten().collect::<Vec<_>>();
-                    let files = futures::stream::iter(files)
-                        .filter_map(|file| async move {
-                            match file {
-                                slack::File::Hosted {
-                                    name,
-                                    title,
-                                    url_private_download,
-                                } => match db.fetch_file(url_private_download).await {
-                                    Ok(file_raw) => {
-                                        info!("file {} size {} MiB", url_private_download, file_raw.inner.len() as f64 / 1024.0 / 1024.0);
-                                        if file_raw.inner.len() > 8 * 1024 * 1024 {
-                                            None
-                                        } else {
-                                            let file = discord::FilePost {
-                                                mime: file_raw.mime.clone(),
-                                                title: title.clone(),
-                                                body: file_raw.inner,
-                                            };
-                                            Some(Ok((name.clone(), file)))
-                                        }
-                                    }
-                                    Err(e) => Some(Err(e)),
-                                },
-                                _ => None,
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                        .await
-                        .into_iter()
-                        .collect::<Result<HashMap<_, _>, _>>()?;
-                    if let Some(thread_ts) = thread_ts && reply_count.is_none() {
-                            debug!("reply to {}", thread_ts);
-                            let thread = sqlx::query_as!(
-                                PostRecord,
-                                "select * from posts where slack_ts = ? and slack_channel_id = ?",
-                                thread_ts,
-                                channel.id
-                            )
-                            .fetch_one(&db.pool)
-                            .with_context(|| format!("ts: {}, channel_id: {}", ts, channel.id))
-                            .await?;
-                            let discord_thread_id =
-                                thread.discord_thread_id.with_context(|| {
-                                    format!(
-                                        "thread {} on {} not found",
-                                        thread.slack_ts, channel.name
-                                    )
-                                })?.into();
-                            let msg = discord::post_message(token, &discord_thread_id, &message, files)
-                                .await?;
-
-                            sqlx::query!(
-                                "insert into posts values (?, ?, ?, ?, ?);",
-                                msg.id,
-                                channel.id,
-                                discord_channel_id,
-                                ts,
-                                None::<String>,
-                            )
-                            .execute(&db.pool)
-                            .await?;
-
-                            if let Some(reply_count) = reply_counts.get(thread_ts) {
-                                let thread = discord::get_channel(token, &discord_thread_id).await?;
-                                if thread.message_count == Some(*reply_count) {
-                                    info!("thread {:?} is over", discord_thread_id);
-                                    archive_channel(token, &discord_thread_id).await?;
-                                }
-                            }
+                let msg_id = &posted.id;
+                // the first message of a forum post is in the post's thread
+                let posted_to = posted.thread.as_deref().unwrap_or(destination);
+                // recorded before the thread exists, so a crash in between does not
+                // post the parent again
+                sqlx::query!(
+                    "insert into posts values (?, ?, ?, ?, ?);",
+                    msg_id,
+                    channel.id,
+                    posted_to,
+                    ts,
+                    posted.thread,
+                )
+                .execute(&db.pool)
+                .await
+                .with_context(|| format!("msg.id: {}", msg_id))?;
+                record_parts(db, &posted, posted_to).await?;
+                if pinned {
+                    sink.pin(posted_to, msg_id).await?;
+                }
+                if let (Some(count), None) = (
+                    reply_count.filter(|count| *count > 0 && !options.inline_threads),
+                    &posted.thread,
+                ) {
+                    debug!("reply_count: {:?}", count);
+                    start_thread(db, sink, destination, msg_id).await?;
+                }
+            }
+            if pending_files {
+                sqlx::query!(
+                    "insert or ignore into pending_files values (?, ?);",
+                    channel.id,
+                    ts
+                )
+                .execute(&db.pool)
+                .await?;
+            }
+            report.posted += 1;
+        } else if let Some(posted) = message_on_db.into_iter().next() {
+            if options.file_pass == FilePass::OnlyFiles {
+                self.post_pending_files(ts, user, files, reply_count, &posted, report)
+                    .await?;
+            }
+            let thread_id = match (posted.discord_thread_id, reply_count) {
+                (Some(thread_id), _) => Some(thread_id),
+                (None, Some(count)) if *count > 0 && !options.inline_threads => {
+                    info!("resume thread of {}", posted.slack_ts);
+                    Some(start_thread(db, sink, &posted.discord_channel_id, &posted.id).await?)
+                }
+                _ => None,
+            };
+            if let (Some(thread_id), Some(reply_count)) = (thread_id, reply_count) {
+                sink.finish_thread(&thread_id, *reply_count).await?;
+            }
+        }
+        Ok(())
+    }
+}
 
-                        } else {
-                            let msg =
-                                discord::post_message(token, discord_channel_id, &message, files).await?;
-                            let thread_id = if let Some(count) = reply_count && *count > 0 {
-                                    debug!("reply_count: {:?}", count);
-                                    Some(
-                                        discord::start_thread(
-                                            token,
-                                            discord_channel_id,
-                                            &msg.id,
-                                            "slack thread",
-                                        )
-                                        .await?
-                                        .id,
-                                    )
-                                } else {
-                                    None
-                                };
+/// Split `messages` into the ones posted in order and groups of thread replies that can be
+/// posted concurrently, each group in order. Threads with a reply also shown in the channel
+/// stay in order, so the channel copy lands where it was on Slack.
+fn split_threads<'a>(
+    messages: &[&'a Message],
+    options: &PostOptions,
+) -> (Vec<&'a Message>, Vec<Vec<&'a Message>>) {
+    if options.workers_per_channel <= 1 || options.inline_threads {
+        return (messages.to_vec(), Vec::new());
+    }
+    let reply_thread = |message: &'a Message| match message {
+        Message::Message {
+            thread_ts: Some(thread_ts),
+            reply_count: None,
+            subtype,
+            ..
+        } => {
+            let broadcast = matches!(subtype, Some(slack::MessageSubType::ThreadBroadcast));
+            Some((thread_ts, broadcast))
+        }
+        _ => None,
+    };
+    let serial_threads = messages
+        .iter()
+        .filter_map(|message| reply_thread(message))
+        .filter(|(_, broadcast)| *broadcast && options.broadcast == BroadcastMode::Both)
+        .map(|(thread_ts, _)| thread_ts)
+        .collect::<HashSet<_>>();
+    let mut sequential = Vec::new();
+    let mut threads: Vec<Vec<&Message>> = Vec::new();
+    let mut thread_index = HashMap::new();
+    for message in messages {
+        match reply_thread(message) {
+            // channel-only broadcasts are posted as channel messages
+            Some((thread_ts, broadcast))
+                if !(serial_threads.contains(thread_ts)
+                    || broadcast && options.broadcast == BroadcastMode::Channel) =>
+            {
+                let index = *thread_index.entry(thread_ts).or_insert_with(|| {
+                    threads.push(Vec::new());
+                    threads.len() - 1
+                });
+                threads[index].push(*message);
+            }
+            _ => sequential.push(*message),
+        }
+    }
+    (sequential, threads)
+}
 
+#[tracing::instrument(skip_all, fields(channel = %channel.name))]
+pub async fn post_channel<S: MessageSink + Sync>(
+    db: &Db,
+    sink: &S,
+    destination: &str,
+    channel: &SlackChannel,
+    users: &HashMap<String, slack::User>,
+    options: &PostOptions,
+) -> Result<ChannelReport, anyhow::Error> {
+    let mut report = ChannelReport::default();
 
-                            sqlx::query!(
-                                "insert into posts values (?, ?, ?, ?, ?);",
-                                msg.id,
-                                channel.id,
-                                discord_channel_id,
-                                ts,
-                                thread_id
-                            )
-                            .execute(&db.pool)
-                            .with_context(|| format!("msg.id: {}", msg.id))
-                            .await?;
-                        }
-                    sleep(Duration::from_millis(1000)).await;
-                } else if let Some(thread_id) = message_on_db.and_then(|msg| msg.discord_thread_id)
+    let dedup_window = options.dedup_window.map(|window| {
+        chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value())
+    });
+    let mut last_kept = None;
+    let mut messages = Vec::new();
+    for message in &channel.messages {
+        let Message::Message {
+            text,
+            ts,
+            reply_count,
+            thread_ts,
+            files,
+            subtype,
+            ..
+        } = message;
+        if options.skip_system_messages && subtype.as_ref().is_some_and(|s| s.is_system()) {
+            continue;
+        }
+        let is_reply = thread_ts.is_some() && reply_count.is_none();
+        match options.thread_pass {
+            ThreadPass::SkipReplies if is_reply => continue,
+            ThreadPass::OnlyReplies if !is_reply => continue,
+            _ => (),
+        }
+        if options.file_pass == FilePass::OnlyFiles && !has_hosted_files(files) {
+            continue;
+        }
+        if let Some(window) = dedup_window {
+            let key = (message.author(), text, thread_ts);
+            match &last_kept {
+                // a parent is kept even if repeated, its replies need it
+                Some((last_key, last_ts))
+                    if *last_key == key
+                        && reply_count.is_none()
+                        && ts.date().signed_duration_since(*last_ts) <= window =>
                 {
-                    let thread = discord::get_channel(token, &thread_id.clone().into()).await?;
-                    if &thread.message_count == reply_count {
-                        info!("thread {} is over", thread_id);
-                        archive_channel(token, &thread_id.into()).await?;
-                    }
+                    debug!("collapse repeated message {}", ts);
+                    report.collapsed += 1;
+                    continue;
                 }
+                _ => last_kept = Some((key, *ts.date())),
             }
         }
+        messages.push(message);
+    }
+
+    let mut grouped = HashSet::new();
+    if let Some(window) = options.group_window {
+        let window =
+            chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value());
+        // the last message of the channel, keyed by None, and of every thread
+        let mut last = HashMap::new();
+        for message in &messages {
+            let Message::Message {
+                ts,
+                thread_ts,
+                reply_count,
+                ..
+            } = message;
+            let stream = match thread_ts {
+                Some(thread_ts) if reply_count.is_none() && !options.inline_threads => {
+                    Some(thread_ts)
+                }
+                _ => None,
+            };
+            let author = message.author();
+            if let Some((last_author, last_date)) = last.insert(stream, (author, *ts.date())) {
+                if last_author == author && ts.date().signed_duration_since(last_date) <= window {
+                    grouped.insert(ts);
+                }
+            }
+        }
+    }
+
+    let poster = ChannelPoster {
+        db,
+        sink,
+        destination,
+        channel,
+        users,
+        options,
+        user_id_to_real_name: user_id_to_real_name(users, options.name_style),
+        attachment_limit: sink.attachment_limit(),
+        reply_counts: channel
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Message {
+                    ts,
+                    reply_count: Some(reply_count),
+                    ..
+                } => Some((ts, *reply_count)),
+                _ => None,
+            })
+            .collect(),
+        grouped,
+    };
+    let handled = AtomicUsize::new(0);
+    let progress = || {
+        if let Some(on_progress) = &options.on_progress {
+            on_progress(ChannelProgress {
+                handled: handled.fetch_add(1, Ordering::Relaxed) + 1,
+                total: messages.len(),
+            });
+        }
+    };
+    let (sequential, threads) = split_threads(&messages, options);
+    for message in sequential {
+        if options.out_of_time() {
+            report.out_of_time = true;
+            return Ok(report);
+        }
+        options.record_post(poster.post_message(message, &mut report).await)?;
+        progress();
+    }
+    if !threads.is_empty() {
+        debug!("post {} threads concurrently", threads.len());
+    }
+    let poster = &poster;
+    let progress = &progress;
+    let thread_reports = futures::stream::iter(threads)
+        .map(|replies| async move {
+            let mut report = ChannelReport::default();
+            for message in replies {
+                if options.out_of_time() {
+                    report.out_of_time = true;
+                    break;
+                }
+                options.record_post(poster.post_message(message, &mut report).await)?;
+                progress();
+            }
+            Ok::<_, anyhow::Error>(report)
+        })
+        .buffer_unordered(options.workers_per_channel.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    for thread_report in thread_reports {
+        let thread_report = thread_report?;
+        report.failed_files.extend(thread_report.failed_files);
+        report.posted += thread_report.posted;
+        report.skipped_replies.extend(thread_report.skipped_replies);
+        report.out_of_order.extend(thread_report.out_of_order);
+        report.out_of_time |= thread_report.out_of_time;
+        for (filetype, bytes) in thread_report.attached_bytes {
+            *report.attached_bytes.entry(filetype).or_default() += bytes;
+        }
+        for (filetype, bytes) in thread_report.oversized_bytes {
+            *report.oversized_bytes.entry(filetype).or_default() += bytes;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(id, name)| (id.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn mentions_of_ids_sharing_a_prefix_resolve_apart() {
+        let dict = names(&[("U1", "alice"), ("U12", "bob")]);
+        assert_eq!(
+            replace_slack_id_to_real_name(&dict, "<@U12> thanks <@U1>"),
+            "@bob thanks @alice"
+        );
+        assert_eq!(
+            replace_slack_id_to_real_name(&dict, "<@U1|alice-old>"),
+            "@alice"
+        );
+    }
+
+    #[test]
+    fn ids_outside_mentions_are_left_alone() {
+        let dict = names(&[("U1", "alice"), ("U12", "bob")]);
+        for text in [
+            "U1 and U12 are ids",
+            "see https://example.com/U12/U1?user=U1",
+            "<https://example.com/team/U1>",
+            "@U1 typed by hand",
+        ] {
+            let rewritten = replace_slack_id_to_real_name(&dict, text);
+            assert!(
+                !rewritten.contains("alice"),
+                "{} became {}",
+                text,
+                rewritten
+            );
+            assert!(!rewritten.contains("bob"), "{} became {}", text, rewritten);
+        }
+    }
+
+    #[test]
+    fn unknown_mentions_stay_as_written() {
+        let dict = names(&[("U1", "alice")]);
+        assert_eq!(
+            replace_slack_id_to_real_name(&dict, "<@U99> and <@U1>"),
+            "<@U99> and @alice"
+        );
+    }
+
+    /// Sink failing every post after the first `fail_after`, and every one with files if
+    /// `reject_files`, remembering what it posted where and what it deleted. Ids start with
+    /// `id_prefix`, to tell apart the posts of sinks sharing a database.
+    #[derive(Default)]
+    struct FlakySink {
+        fail_after: usize,
+        reject_files: bool,
+        id_prefix: &'static str,
+        posted: std::sync::Mutex<Vec<String>>,
+        channels: std::sync::Mutex<Vec<String>>,
+        contents: std::sync::Mutex<Vec<String>>,
+        flags: std::sync::Mutex<Vec<u64>>,
+        /// Names of the files of every post, in order.
+        filenames: std::sync::Mutex<Vec<String>>,
+        deleted: std::sync::Mutex<Vec<String>>,
+        deleted_threads: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl MessageSink for FlakySink {
+        async fn post(
+            &self,
+            channel: &str,
+            message: &discord::MessagePost,
+            files: Vec<(String, discord::FilePost)>,
+        ) -> Result<sink::Posted, anyhow::Error> {
+            let mut posted = self.posted.lock().unwrap();
+            if posted.len() >= self.fail_after {
+                anyhow::bail!("post {} fails", posted.len());
+            }
+            if self.reject_files && !files.is_empty() {
+                anyhow::bail!("files are rejected");
+            }
+            let id = format!("{}m{}", self.id_prefix, posted.len());
+            posted.push(id.clone());
+            self.channels.lock().unwrap().push(channel.to_owned());
+            self.contents.lock().unwrap().push(message.content.clone());
+            self.flags.lock().unwrap().push(message.flags);
+            self.filenames
+                .lock()
+                .unwrap()
+                .extend(files.into_iter().map(|(name, _)| name));
+            Ok(sink::Posted {
+                id,
+                timestamp: None,
+                thread: None,
+            })
+        }
+
+        async fn start_thread(
+            &self,
+            _channel: &str,
+            message: &str,
+            _name: &str,
+        ) -> Result<String, anyhow::Error> {
+            Ok(format!("t-{}", message))
+        }
+
+        async fn delete(&self, _channel: &str, message: &str) -> Result<(), anyhow::Error> {
+            self.deleted.lock().unwrap().push(message.to_owned());
+            Ok(())
+        }
+
+        async fn delete_thread(&self, thread: &str) -> Result<(), anyhow::Error> {
+            self.deleted_threads.lock().unwrap().push(thread.to_owned());
+            Ok(())
+        }
+    }
+
+    /// Content split into `parts` parts.
+    fn long_message(parts: usize) -> discord::MessagePost {
+        let line = "x".repeat(99);
+        discord::MessagePost {
+            content: vec![line; parts * MESSAGE_CONTENT_MAX_CHARS / 100].join("\n"),
+            flags: 0,
+        }
+    }
+
+    async fn post_long(
+        sink: &FlakySink,
+        forum_post: Option<&sink::ForumPost>,
+        parts: usize,
+    ) -> Result<PostedParts, anyhow::Error> {
+        let message = long_message(parts);
+        assert_eq!(split_content(&message.content).len(), parts);
+        post_with_files(
+            sink,
+            "C1",
+            forum_post,
+            &message,
+            Vec::new(),
+            &[],
+            &slack::TimeStamp::from_unix(0, 0).unwrap(),
+            0,
+            &mut ChannelReport::default(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn parts_posted_ahead_of_a_failed_one_are_deleted() {
+        let sink = FlakySink {
+            fail_after: 2,
+            ..Default::default()
+        };
+        assert!(post_long(&sink, None, 3).await.is_err());
+        assert_eq!(*sink.deleted.lock().unwrap(), ["m0", "m1"]);
+        assert!(sink.deleted_threads.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_forum_post_of_a_failed_message_is_deleted() {
+        let sink = FlakySink {
+            fail_after: 1,
+            ..Default::default()
+        };
+        let forum_post = sink::ForumPost {
+            name: "topic".to_owned(),
+            tags: Vec::new(),
+        };
+        assert!(post_long(&sink, Some(&forum_post), 2).await.is_err());
+        assert_eq!(*sink.deleted_threads.lock().unwrap(), ["t-m0"]);
+        assert!(sink.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nothing_is_deleted_when_every_part_is_posted() {
+        let sink = FlakySink {
+            fail_after: 2,
+            ..Default::default()
+        };
+        let posted = post_long(&sink, None, 2).await.unwrap();
+        assert_eq!(posted.id, "m0");
+        assert_eq!(posted.rest, ["m1"]);
+        assert!(sink.deleted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_post_without_its_files_keeps_its_flags() {
+        let sink = FlakySink {
+            fail_after: 1,
+            reject_files: true,
+            ..Default::default()
+        };
+        let message = discord::MessagePost {
+            content: "see https://example.com".to_owned(),
+            flags: discord::MESSAGE_FLAG_SUPPRESS_EMBEDS,
+        };
+        let file = discord::FilePost {
+            mime: "text/plain".to_owned(),
+            title: "notes.txt".to_owned(),
+            body: b"notes".to_vec(),
+        };
+        let mut report = ChannelReport::default();
+        post_with_files(
+            &sink,
+            "C1",
+            None,
+            &message,
+            vec![("notes.txt".to_owned(), file)],
+            &["https://files.slack.com/notes.txt".to_owned()],
+            &slack::TimeStamp::from_unix(0, 0).unwrap(),
+            0,
+            &mut report,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *sink.flags.lock().unwrap(),
+            [discord::MESSAGE_FLAG_SUPPRESS_EMBEDS]
+        );
+        assert_eq!(report.failed_files.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn notes_on_files_left_out_of_a_full_message_go_in_another_part() {
+        let sink = FlakySink {
+            fail_after: 2,
+            reject_files: true,
+            ..Default::default()
+        };
+        let message = discord::MessagePost {
+            content: "x".repeat(MESSAGE_CONTENT_MAX_CHARS),
+            flags: 0,
+        };
+        let file = discord::FilePost {
+            mime: "text/plain".to_owned(),
+            title: "notes.txt".to_owned(),
+            body: b"notes".to_vec(),
+        };
+        let mut report = ChannelReport::default();
+        let posted = post_with_files(
+            &sink,
+            "C1",
+            None,
+            &message,
+            vec![("notes.txt".to_owned(), file)],
+            &["https://files.slack.com/notes.txt".to_owned()],
+            &slack::TimeStamp::from_unix(0, 0).unwrap(),
+            0,
+            &mut report,
+        )
+        .await
+        .unwrap();
+        assert_eq!(posted.rest.len(), 1);
+        let contents = sink.contents.lock().unwrap();
+        assert_eq!(contents.len(), 2);
+        assert!(contents
+            .iter()
+            .all(|content| content.chars().count() <= MESSAGE_CONTENT_MAX_CHARS));
+        assert!(contents[1].contains("(failed to attach notes.txt)"));
+        assert!(sink.deleted.lock().unwrap().is_empty());
+        assert_eq!(report.failed_files.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn files_refused_after_their_retries_are_all_left_out_without_going_over_the_limit() {
+        let sink = FlakySink {
+            fail_after: 3,
+            reject_files: true,
+            ..Default::default()
+        };
+        let message = discord::MessagePost {
+            content: "x".repeat(MESSAGE_CONTENT_MAX_CHARS),
+            flags: 0,
+        };
+        let file = |title: &str| discord::FilePost {
+            mime: "text/plain".to_owned(),
+            title: title.to_owned(),
+            body: b"notes".to_vec(),
+        };
+        let mut report = ChannelReport::default();
+        post_with_files(
+            &sink,
+            "C1",
+            None,
+            &message,
+            vec![
+                ("a.txt".to_owned(), file("a.txt")),
+                ("b.txt".to_owned(), file("b.txt")),
+            ],
+            &[
+                "https://files.slack.com/a.txt".to_owned(),
+                "https://files.slack.com/b.txt".to_owned(),
+            ],
+            &slack::TimeStamp::from_unix(0, 0).unwrap(),
+            1,
+            &mut report,
+        )
+        .await
+        .unwrap();
+        let contents = sink.contents.lock().unwrap();
+        assert_eq!(contents.len(), 2);
+        assert!(contents
+            .iter()
+            .all(|content| content.chars().count() <= MESSAGE_CONTENT_MAX_CHARS));
+        assert!(contents[1].contains("(failed to attach a.txt)\n(failed to attach b.txt)"));
+        assert!(sink.deleted.lock().unwrap().is_empty());
+        let titles = report
+            .failed_files
+            .iter()
+            .map(|failed| failed.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["a.txt", "b.txt"]);
+    }
+
+    fn encoded(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(width, height)
+            .write_to(&mut out, format)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn downscaled_images_fit_the_max_dimension() {
+        let (name, file) =
+            downscale_image("shot.gif", &encoded(64, 32, image::ImageFormat::Gif), 16).unwrap();
+        assert_eq!(name, "shot.png");
+        assert_eq!(file.mime, "image/png");
+        let preview = image::load_from_memory(&file.body).unwrap();
+        assert_eq!((preview.width(), preview.height()), (16, 8));
+
+        let (name, file) =
+            downscale_image("photo.jpg", &encoded(32, 64, image::ImageFormat::Jpeg), 16).unwrap();
+        assert_eq!(name, "photo.jpg");
+        assert_eq!(file.mime, "image/jpeg");
+    }
+
+    #[test]
+    fn downscaling_what_is_no_image_fails() {
+        assert!(downscale_image("notes.png", b"not an image", 16).is_err());
+    }
+
+    /// Zipped export of `entries`, named as in the archive.
+    fn export(entries: &[(&str, &str)]) -> zip::ZipArchive<std::io::Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            std::io::Write::write_all(&mut writer, content.as_bytes()).unwrap();
+        }
+        zip::ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    fn load(entries: &[(&str, &str)]) -> Vec<SlackChannel> {
+        get_channels_stream(
+            &mut export(entries),
+            &HashMap::new(),
+            &LoadOptions::default(),
+            &mut |_| {},
+        )
+        .unwrap()
+    }
+
+    fn stamps(channel: &SlackChannel) -> Vec<slack::TimeStamp> {
+        channel
+            .messages
+            .iter()
+            .map(|message| message.ts().clone())
+            .collect()
+    }
+
+    fn ts(src: &str) -> slack::TimeStamp {
+        slack::TimeStamp::parse(src).unwrap()
+    }
+
+    #[test]
+    fn messages_sharing_a_ts_are_told_apart() {
+        let channels = load(&[
+            ("channels.json", r#"[{"id": "C1", "name": "general"}]"#),
+            (
+                "general/2022-01-01.json",
+                r#"[
+                    {"type": "message", "user": "U1", "text": "first", "ts": "1641000000.000100"},
+                    {"type": "message", "user": "U2", "text": "second", "ts": "1641000000.000100"},
+                    {"type": "message", "user": "U1", "text": "third", "ts": "1641000000.000100"},
+                    {"type": "message", "user": "U1", "text": "later", "ts": "1641000001.000100"}
+                ]"#,
+            ),
+        ]);
+        assert_eq!(
+            stamps(&channels[0]),
+            [
+                ts("1641000000.000100"),
+                ts("1641000000.000100#1"),
+                ts("1641000000.000100#2"),
+                ts("1641000001.000100"),
+            ]
+        );
+    }
+
+    #[test]
+    fn channels_sharing_a_name_stay_apart() {
+        let entries = [
+            ("channels.json", r#"[{"id": "C1", "name": "general"}]"#),
+            ("groups.json", r#"[{"id": "G1", "name": "general"}]"#),
+            (
+                "general/2022-01-01.json",
+                r#"[{"type": "message", "user": "U1", "text": "hi", "ts": "1641000000.000100"}]"#,
+            ),
+        ];
+        let channels = get_channels_stream(
+            &mut export(&entries),
+            &HashMap::new(),
+            &LoadOptions {
+                include_private: true,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap();
+        let loaded = channels
+            .iter()
+            .map(|channel| (channel.id.as_str(), channel.kind, channel.messages.len()))
+            .collect::<Vec<_>>();
+        // the directory is read as the channel listed last, the other keeps its own identity
+        assert_eq!(
+            loaded,
+            [
+                ("C1", ChannelKind::Public, 0),
+                ("G1", ChannelKind::Private, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_day_files_load_each_message_once() {
+        let channels = load(&[
+            ("channels.json", r#"[{"id": "C1", "name": "general"}]"#),
+            (
+                "general/2022-01-01.json",
+                r#"[
+                    {"type": "message", "user": "U1", "text": "one", "ts": "1641000000.000100"},
+                    {"type": "message", "user": "U1", "text": "two", "ts": "1641000001.000100"}
+                ]"#,
+            ),
+            (
+                "general/2022-01-01 (1).json",
+                r#"[
+                    {"type": "message", "user": "U1", "text": "two", "ts": "1641000001.000100"},
+                    {"type": "message", "user": "U1", "text": "three", "ts": "1641000002.000100"}
+                ]"#,
+            ),
+        ]);
+        assert_eq!(
+            stamps(&channels[0]),
+            [
+                ts("1641000000.000100"),
+                ts("1641000001.000100"),
+                ts("1641000002.000100"),
+            ]
+        );
+    }
+
+    #[test]
+    fn mimes_lose_their_parameters() {
+        for (content_type, mime) in [
+            ("text/plain; charset=utf-8", "text/plain"),
+            ("Text/HTML;charset=\"ISO-8859-1\"", "text/html"),
+            ("image/svg+xml", "image/svg+xml"),
+            (" application/json ; q=1", "application/json"),
+        ] {
+            assert_eq!(normalize_mime(content_type).as_deref(), Some(mime));
+        }
+    }
+
+    #[test]
+    fn mimes_without_a_subtype_are_invalid() {
+        for content_type in ["", "text", "text/", "/plain", "text/plain/x", "te xt/plain"] {
+            assert_eq!(normalize_mime(content_type), None, "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn unicode_directories_are_found_whatever_their_form_and_separator() {
+        let message =
+            r#"[{"type": "message", "user": "U1", "text": "hi", "ts": "1641000000.000100"}]"#;
+        // ガイド decomposed, as macOS stores it, and 開発 behind a Windows separator
+        let channels = load(&[
+            (
+                "channels.json",
+                r#"[{"id": "C1", "name": "ガイド"}, {"id": "C2", "name": "開発"}]"#,
+            ),
+            (
+                "\u{30ab}\u{3099}\u{30a4}\u{30c8}\u{3099}/2022-01-01.json",
+                message,
+            ),
+            ("開発\\2022-01-01.json", message),
+        ]);
+        let loaded = channels
+            .iter()
+            .map(|channel| (channel.name.as_str(), channel.messages.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(loaded, [("ガイド", 1), ("開発", 1)]);
+    }
+
+    #[test]
+    fn entry_names_split_into_directory_and_file() {
+        assert_eq!(
+            split_entry_name("general/2022-01-01.json"),
+            Some(("general".to_owned(), "2022-01-01.json".to_owned()))
+        );
+        assert_eq!(
+            split_entry_name("general/"),
+            Some(("general".to_owned(), String::new()))
+        );
+        assert_eq!(split_entry_name("users.json"), None);
+        assert_eq!(split_entry_name("a/b/c.json"), None);
+    }
+
+    fn rejected(body: &str) -> discord::Error {
+        discord::Error::Status {
+            status: 400,
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn channel_limit_responses_become_provision_errors() {
+        let full_guild = rejected(
+            r#"{"message": "Maximum number of guild channels reached (500)", "code": 30013}"#,
+        );
+        assert!(matches!(
+            channel_limit_error(full_guild, Some("general")).downcast_ref(),
+            Some(ProvisionError::ChannelLimit)
+        ));
+        let full_category = rejected(
+            r#"{"message": "Invalid Form Body", "code": 50035, "errors": {"parent_id": {"_errors": [{"code": "CHANNEL_PARENT_MAX_CHANNELS", "message": "Maximum number of channels in category reached (50)"}]}}}"#,
+        );
+        assert!(matches!(
+            channel_limit_error(full_category, Some("general")).downcast_ref(),
+            Some(ProvisionError::CategoryLimit(category)) if category == "general"
+        ));
+    }
+
+    #[test]
+    fn other_rejections_are_kept() {
+        let error = channel_limit_error(
+            rejected(r#"{"message": "Missing Permissions", "code": 50013}"#),
+            Some("general"),
+        );
+        assert!(error.downcast_ref::<ProvisionError>().is_none());
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(discord::Error::Status { status: 400, .. })
+        ));
+    }
+
+    #[test]
+    fn headers_of_posts_without_a_user_name_unknown() {
+        let share: Message = serde_json::from_str(
+            r#"{"type": "message", "upload": true, "text": "", "ts": "1641000000.000100",
+                "files": [{"id": "F1", "mode": "tombstone"}]}"#,
+        )
+        .unwrap();
+        let header = render_message(
+            &PostOptions::default(),
+            &names(&[("U1", "alice")]),
+            share.author(),
+            share.ts(),
+            "",
+            false,
+        );
+        assert!(header.starts_with("**unknown** "), "{}", header);
+    }
+
+    /// `general` holding `messages`, as Slack JSON.
+    fn channel_of(messages: &str) -> SlackChannel {
+        SlackChannel {
+            id: "C1".to_owned(),
+            name: "general".to_owned(),
+            kind: ChannelKind::Public,
+            members: Vec::new(),
+            archived: false,
+            messages: serde_json::from_str(messages).unwrap(),
+        }
+    }
+
+    /// A reply to a channel join, a system message left out by `skip_system_messages`.
+    const ORPHAN_REPLY: &str = r#"[
+        {"type": "message", "subtype": "channel_join", "user": "U2",
+         "text": "<@U2> has joined the channel", "ts": "1641000000.000100"},
+        {"type": "message", "user": "U1", "text": "welcome",
+         "ts": "1641000001.000100", "thread_ts": "1641000000.000100"}]"#;
+
+    #[tokio::test]
+    async fn replies_to_threads_never_posted_are_skipped_if_asked() {
+        let db = Db::in_memory().await.unwrap();
+        let sink = FlakySink {
+            fail_after: usize::MAX,
+            ..Default::default()
+        };
+        let options = PostOptions {
+            continue_on_error: true,
+            skip_system_messages: true,
+            ..Default::default()
+        };
+        let report = post_channel(
+            &db,
+            &sink,
+            "D1",
+            &channel_of(ORPHAN_REPLY),
+            &HashMap::new(),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.skipped_replies, [ts("1641000001.000100")]);
+        assert!(sink.posted.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replies_to_threads_never_posted_fail_by_default() {
+        let db = Db::in_memory().await.unwrap();
+        let sink = FlakySink {
+            fail_after: usize::MAX,
+            ..Default::default()
+        };
+        let error = post_channel(
+            &db,
+            &sink,
+            "D1",
+            &channel_of(ORPHAN_REPLY),
+            &HashMap::new(),
+            &PostOptions {
+                skip_system_messages: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(PostError::ThreadParentMissing { slack_ts, channel })
+                if *slack_ts == ts("1641000000.000100") && channel == "general"
+        ));
+    }
+
+    #[test]
+    fn mass_mentions_cannot_ping() {
+        let dict = names(&[]);
+        for (text, defused) in [
+            ("@everyone look", "@\u{200B}everyone look"),
+            ("ping @here!", "ping @\u{200B}here!"),
+            (
+                "<!everyone> and <!here|here>",
+                "@\u{200B}everyone and @\u{200B}here",
+            ),
+            ("<!channel>", "@channel"),
+        ] {
+            assert_eq!(replace_slack_id_to_real_name(&dict, text), defused);
+        }
+    }
+
+    fn named(names: &[&str]) -> Vec<(String, discord::FilePost)> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let file = discord::FilePost {
+                    mime: "text/plain".to_owned(),
+                    title: name.to_string(),
+                    body: vec![index as u8],
+                };
+                (name.to_string(), file)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_named_attachments_all_get_through() {
+        let files = dedup_filenames(named(&["a.txt", "a.txt", "README", "a.txt", "README"]));
+        let files = files
+            .iter()
+            .map(|(name, file)| (name.as_str(), file.body[0]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            [
+                ("a.txt", 0),
+                ("a (2).txt", 1),
+                ("README", 2),
+                ("a (3).txt", 3),
+                ("README (2)", 4),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn same_named_hosted_files_of_a_message_are_all_posted() {
+        let db = Db::in_memory().await.unwrap();
+        for url in [
+            "https://files.slack.com/F1/a.txt",
+            "https://files.slack.com/F2/a.txt",
+        ] {
+            sqlx::query("insert into files (url, inner, mime) values (?, ?, ?)")
+                .bind(url)
+                .bind(url.as_bytes())
+                .bind("text/plain")
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        let channel = channel_of(
+            r#"[{"type": "message", "user": "U1", "text": "two of them", "ts": "1641000000.000100",
+                "files": [
+                    {"id": "F1", "mode": "hosted", "name": "a.txt", "title": "a.txt",
+                     "url_private_download": "https://files.slack.com/F1/a.txt"},
+                    {"id": "F2", "mode": "hosted", "name": "a.txt", "title": "a.txt",
+                     "url_private_download": "https://files.slack.com/F2/a.txt"}
+                ]}]"#,
+        );
+        let sink = FlakySink {
+            fail_after: usize::MAX,
+            ..Default::default()
+        };
+        post_channel(
+            &db,
+            &sink,
+            "D1",
+            &channel,
+            &HashMap::new(),
+            &PostOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*sink.filenames.lock().unwrap(), ["a.txt", "a (2).txt"]);
+    }
+
+    #[test]
+    fn names_taken_by_a_rename_are_renamed_too() {
+        let files = dedup_filenames(named(&["a.txt", "a.txt", "a (2).txt", ".env", ".env"]));
+        let names = files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["a.txt", "a (2).txt", "a (2) (2).txt", ".env", ".env (2)"]
+        );
+    }
+
+    /// The guild's own `general` category, which has nothing to do with the migration.
+    fn foreign_category() -> Vec<ChannelGet> {
+        serde_json::from_str(r#"[{"id": "K0", "name": "general", "type": 4, "parent_id": null}]"#)
+            .unwrap()
+    }
+
+    async fn provision_general(
+        responses: Vec<(u16, &'static str)>,
+        dedicated: bool,
+    ) -> (HashMap<String, discord::ChannelId>, Vec<String>) {
+        let (client, server) = discord::tests::serve(responses).await;
+        let db = Db::in_memory().await.unwrap();
+        let provisioned = provision_channel_categories(
+            &client,
+            &discord::GuildId::new("G1".to_owned()),
+            Some(&db),
+            &foreign_category(),
+            &HashSet::from(["general"]),
+            dedicated,
+            None,
+        )
+        .await
+        .unwrap();
+        (provisioned, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn dedicated_categories_leave_same_named_ones_alone() {
+        const CREATED: &str = r#"{"id": "K1", "name": "general", "type": 4, "parent_id": null}"#;
+        let (provisioned, requests) = provision_general(vec![(201, CREATED)], true).await;
+        assert_eq!(provisioned["general"].to_string(), "K1");
+        assert_eq!(requests, ["POST /guilds/G1/channels HTTP/1.1"]);
+    }
+
+    #[tokio::test]
+    async fn same_named_categories_are_adopted_by_default() {
+        let (provisioned, requests) = provision_general(Vec::new(), false).await;
+        assert_eq!(provisioned["general"].to_string(), "K0");
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn links_repeating_their_url_become_bare() {
+        for (slack, discord) in [
+            ("<https://example.com>", "https://example.com"),
+            (
+                "<https://example.com/a|https://example.com/a>",
+                "https://example.com/a",
+            ),
+            (
+                "<https://example.com/a|example.com/a>",
+                "https://example.com/a",
+            ),
+            ("<https://example.com|>", "https://example.com"),
+            (
+                "<mailto:bob@example.com|bob@example.com>",
+                "bob@example.com",
+            ),
+        ] {
+            assert_eq!(rewrite_slack_links(slack), discord);
+        }
+    }
+
+    #[test]
+    fn labeled_links_become_masked() {
+        assert_eq!(
+            rewrite_slack_links("see <https://example.com/docs|the docs> and <https://x.com>"),
+            "see [the docs](<https://example.com/docs>) and https://x.com"
+        );
+        assert_eq!(
+            rewrite_slack_links("<mailto:bob@example.com|Bob>"),
+            "[Bob](<mailto:bob@example.com>)"
+        );
+    }
+
+    #[test]
+    fn text_without_links_is_kept() {
+        for text in ["no links", "<#C1|general>", "a < b > c"] {
+            assert_eq!(rewrite_slack_links(text), text);
+        }
+    }
+
+    #[tokio::test]
+    async fn files_past_the_cache_cap_evict_the_least_recently_used() {
+        let (endpoint, server) =
+            discord::tests::serve_http(vec![(200, "aaaaaa"), (200, "bbbbbb"), (200, "cccccc")])
+                .await;
+        let mut db = Db::in_memory().await.unwrap();
+        db.cache_max_bytes = Some(14);
+        let url = |name| format!("{}/{}", endpoint, name);
+        for name in ["a", "b", "a", "c"] {
+            db.fetch_file(&url(name)).await.unwrap();
+            // last_accessed is in milliseconds
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // `a` came from the cache the second time, so `b` is the oldest
+        assert_eq!(server.await.unwrap().len(), 3);
+        assert!(db.is_cached(&url("a")).await.unwrap());
+        assert!(!db.is_cached(&url("b")).await.unwrap());
+        assert!(db.is_cached(&url("c")).await.unwrap());
+        assert_eq!(db.cache_size_bytes().await.unwrap(), 12);
+    }
+
+    #[tokio::test]
+    async fn the_file_just_stored_is_kept_over_the_cap() {
+        let (endpoint, _server) = discord::tests::serve_http(vec![(200, "aaaaaa")]).await;
+        let mut db = Db::in_memory().await.unwrap();
+        db.cache_max_bytes = Some(1);
+        let url = format!("{}/a", endpoint);
+        db.fetch_file(&url).await.unwrap();
+        assert!(db.is_cached(&url).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn files_without_a_download_url_are_linked() {
+        let channel = channel_of(
+            r#"[{"type": "message", "user": "U1", "text": "minutes", "ts": "1641000000.000100",
+                "files": [
+                    {"id": "F1", "mode": "hosted", "name": "minutes.pdf", "title": "Minutes",
+                     "permalink": "https://example.slack.com/files/U1/F1/minutes.pdf"},
+                    {"id": "F2", "mode": "hosted", "name": "plan.pdf", "title": "Plan",
+                     "permalink": "https://example.slack.com/files/U1/F2/plan.pdf",
+                     "permalink_public": "https://slack-files.com/T1-F2-abc"}
+                ]}]"#,
+        );
+        let db = Db::in_memory().await.unwrap();
+        let sink = FlakySink {
+            fail_after: usize::MAX,
+            ..Default::default()
+        };
+        post_channel(
+            &db,
+            &sink,
+            "D1",
+            &channel,
+            &HashMap::new(),
+            &PostOptions::default(),
+        )
+        .await
+        .unwrap();
+        let contents = sink.contents.lock().unwrap();
+        assert_eq!(contents.len(), 1);
+        assert!(
+            contents[0].contains("(Minutes at https://example.slack.com/files/U1/F1/minutes.pdf)"),
+            "{}",
+            contents[0]
+        );
+        assert!(
+            contents[0].contains("(Plan at https://slack-files.com/T1-F2-abc)"),
+            "{}",
+            contents[0]
+        );
+    }
+
+    #[test]
+    fn channel_dirs_leave_out_private_channels_and_dms() {
+        let message = "[]";
+        let dirs = channel_dirs(&mut export(&[
+            ("groups.json", r#"[{"id": "G1", "name": "secret"}]"#),
+            (
+                "mpims.json",
+                r#"[{"id": "G2", "name": "mpdm-alice--bob-1", "members": []}]"#,
+            ),
+            ("dms.json", r#"[{"id": "D1", "members": []}]"#),
+            ("general/2022-01-01.json", message),
+            ("random/2022-01-01.json", message),
+            ("secret/2022-01-01.json", message),
+            ("mpdm-alice--bob-1/2022-01-01.json", message),
+            ("D1/2022-01-01.json", message),
+            ("users.json", message),
+        ]))
+        .unwrap();
+        assert_eq!(dirs.into_iter().collect::<Vec<_>>(), ["general", "random"]);
+    }
+
+    /// A thread whose reply was also sent to the channel.
+    const BROADCAST_THREAD: &str = r#"[
+        {"type": "message", "user": "U1", "text": "question", "ts": "1641000000.000100",
+         "reply_count": 1, "thread_ts": "1641000000.000100"},
+        {"type": "message", "subtype": "thread_broadcast", "user": "U2", "text": "answer",
+         "ts": "1641000001.000100", "thread_ts": "1641000000.000100"}
+    ]"#;
+
+    #[tokio::test]
+    async fn broadcasts_stopped_between_their_copies_resume_with_the_missing_one() {
+        let db = Db::in_memory().await.unwrap();
+        let channel = channel_of(BROADCAST_THREAD);
+        // the parent and the thread copy get through, the channel copy does not
+        let stopped = FlakySink {
+            fail_after: 2,
+            ..Default::default()
+        };
+        let options = PostOptions::default();
+        assert!(
+            post_channel(&db, &stopped, "D1", &channel, &HashMap::new(), &options)
+                .await
+                .is_err()
+        );
+        assert_eq!(*stopped.channels.lock().unwrap(), ["D1", "t-m0"]);
+
+        let resumed = FlakySink {
+            fail_after: usize::MAX,
+            id_prefix: "r",
+            ..Default::default()
+        };
+        post_channel(&db, &resumed, "D1", &channel, &HashMap::new(), &options)
+            .await
+            .unwrap();
+        assert_eq!(*resumed.channels.lock().unwrap(), ["D1"]);
+        assert_eq!(db.posted_count("C1").await.unwrap(), 2);
+
+        // with both copies recorded, nothing is posted again
+        let again = FlakySink {
+            fail_after: usize::MAX,
+            id_prefix: "a",
+            ..Default::default()
+        };
+        post_channel(&db, &again, "D1", &channel, &HashMap::new(), &options)
+            .await
+            .unwrap();
+        assert!(again.posted.lock().unwrap().is_empty());
     }
-    Ok(())
 }
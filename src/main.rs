@@ -1,76 +1,1683 @@
 use anyhow::Context;
 use clap::Parser;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use slack_to_discord::archive::ExportArchive;
+use slack_to_discord::checkpoint::Checkpoint;
+use slack_to_discord::source::{users_by_id, ApiSource, ExportSource, SlackSource};
 use slack_to_discord::{slack, ChannelConfig};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{fs, io};
-use tracing::info;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+static VERSION: Lazy<String> = Lazy::new(|| {
+    format!(
+        "{} (schema {})",
+        env!("CARGO_PKG_VERSION"),
+        slack_to_discord::SCHEMA_VERSION
+    )
+});
+
+#[derive(Debug, Clone, Copy)]
+enum LogFormat {
+    Text,
+    /// One JSON object per event, with the fields of its spans.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format {} (text or json)", s)),
+        }
+    }
+}
+
+/// Format of a channel config.
+#[derive(Debug, Clone, Copy)]
+enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl std::str::FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err(format!("unknown config format {} (json, toml or yaml)", s)),
+        }
+    }
+}
+
+impl ConfigFormat {
+    /// Format of a config file by its extension, JSON unless `.toml`, `.yaml` or `.yml`.
+    fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::Toml,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Json,
+        }
+    }
+
+    /// Parse a config, with the environment variables it refers to expanded.
+    fn parse(self, src: &str) -> Result<Config, anyhow::Error> {
+        let mut value: serde_json::Value = match self {
+            Self::Json => serde_json::from_str(src)?,
+            Self::Toml => toml::from_str(src)?,
+            Self::Yaml => serde_yaml::from_str(src)?,
+        };
+        expand_env(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    fn print(self, config: &Config) -> Result<String, anyhow::Error> {
+        Ok(match self {
+            // through Value so keys come out sorted
+            Self::Json => serde_json::to_string_pretty(&serde_json::to_value(config)?)?,
+            // toml has no null, so unset options are left out
+            Self::Toml => toml::to_string_pretty(&toml::Value::try_from(config)?)?,
+            Self::Yaml => serde_yaml::to_string(&serde_json::to_value(config)?)?,
+        })
+    }
+}
+
+/// A duration like `90`, `90s`, `30m` or `2h`, seconds without a unit.
+#[derive(Debug, Clone, Copy)]
+struct HumanDuration(Duration);
+
+impl std::str::FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => s.split_at(index),
+            None => (s, "s"),
+        };
+        let value: u64 = value
+            .parse()
+            .map_err(|_| format!("invalid duration {} (like 90s, 30m or 2h)", s))?;
+        let secs = match unit {
+            "s" => value,
+            "m" => value * 60,
+            "h" => value * 60 * 60,
+            _ => return Err(format!("unknown unit in duration {} (s, m or h)", s)),
+        };
+        Ok(Self(Duration::from_secs(secs)))
+    }
+}
 
 #[derive(clap::Parser, Debug)]
+#[clap(subcommand_negates_reqs = true, version = VERSION.as_str())]
 struct Opts {
-    #[clap(short, long)]
-    msg: PathBuf,
-    #[clap(short, long)]
-    db: String,
-    #[clap(short, long)]
-    config: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// `text` or `json`, for runs whose logs are collected by CI or a container runtime
+    #[clap(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+    /// Read the bot token from this file instead of BOT_TOKEN, which process listings of some
+    /// setups show
+    #[clap(long, global = true, conflicts_with = "token-stdin")]
+    token_file: Option<PathBuf>,
+    /// Read the bot token from the first line of stdin instead of BOT_TOKEN
+    #[clap(long, global = true)]
+    token_stdin: bool,
+    /// Read the guild id from this file instead of GUILD_ID
+    #[clap(long, global = true)]
+    guild_id_file: Option<PathBuf>,
+    /// Show progress bars of the run and of the channel being posted, with log lines above
+    /// them. Nothing is drawn when stderr is not a terminal
+    #[clap(long)]
+    progress: bool,
+    #[clap(short, long, required_unless_present = "from-api")]
+    msg: Option<PathBuf>,
+    /// Read a live workspace through the Slack Web API with SLACK_TOKEN instead of --msg
+    #[clap(long, conflicts_with = "msg")]
+    from_api: bool,
+    #[clap(
+        short,
+        long,
+        required_unless_present = "output-dir",
+        conflicts_with = "output-dir"
+    )]
+    db: Option<String>,
+    /// Channel config, JSON unless named `.toml`, `.yaml` or `.yml`. `${VAR}` in any string or
+    /// key in it is replaced by the environment variable VAR, which has to be set
+    #[clap(
+        short,
+        long,
+        required_unless_present_any = &["output-dir", "db-readonly"]
+    )]
+    config: Option<PathBuf>,
+    /// Milliseconds to wait on a locked database before failing
+    #[clap(long, default_value_t = 5000)]
+    db_busy_timeout_ms: u64,
+    /// Open the database read-only and only report what was posted of each channel, like
+    /// `verify`, without touching Discord
+    #[clap(long, conflicts_with = "output-dir")]
+    db_readonly: bool,
+    /// Database connections kept open at most. SQLite has one writer at a time, so a small
+    /// pool in WAL mode is enough even with concurrent thread workers
+    #[clap(long, default_value_t = 4)]
+    db_connections: u32,
+    /// Check cached files with conditional requests before reusing them, for sources whose
+    /// files may change
+    #[clap(long)]
+    revalidate_files: bool,
+    /// Bytes of downloaded files the database keeps at most, evicting the least recently used
+    /// ones past it. Unbounded unless set
+    #[clap(long)]
+    cache_max_bytes: Option<u64>,
+    /// Write each channel as Markdown into this directory instead of posting to Discord. Posts
+    /// are kept in memory, as the files are written anew on every run
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+    /// Requests per second allowed across all Discord API calls
+    #[clap(long, default_value_t = slack_to_discord::discord::DISCORD_GLOBAL_RATE_LIMIT)]
+    global_rate_limit: u32,
+    /// Stay under this many requests per second in total; 0 disables the cap. 429 responses are
+    /// still retried, and each message post keeps its fixed one second pause
+    #[clap(long)]
+    rate_limit_rps: Option<u32>,
+    /// Bundle attachments beyond this count per message into a single zip
+    #[clap(long)]
+    max_attachment_count: Option<usize>,
+    /// Times a request rate limited by Discord is retried before the channel fails
+    #[clap(long, default_value_t = 5)]
+    max_retries: u32,
+    /// Upload files of this mime type as .txt; repeat to add more to the defaults
+    #[clap(long)]
+    deny_mime: Vec<String>,
+    /// Upload files of this mime type as they are even if denied by default
+    #[clap(long)]
+    allow_mime: Vec<String>,
+    /// Abort on the first channel that fails (default)
+    #[clap(long, conflicts_with = "continue-on-error")]
+    fail_fast: bool,
+    /// Log a failing channel and move on, exiting nonzero at the end. Replies to threads that
+    /// were never posted are skipped instead of failing their channel
+    #[clap(long)]
+    continue_on_error: bool,
+    /// Give up on the whole run, --continue-on-error or not, once this many messages in a
+    /// row failed to post, as Discord is then most likely down or unreachable
+    #[clap(long, default_value_t = 10)]
+    max_consecutive_failures: usize,
+    /// Also migrate group DMs (mpims.json) and DMs (dms.json)
+    #[clap(long)]
+    include_dms: bool,
+    /// Also migrate private channels (groups.json) as channels hidden from @everyone
+    #[clap(long)]
+    include_private: bool,
+    /// Skip channels whose name sorts before this one
+    #[clap(long)]
+    start_channel: Option<String>,
+    /// Fail instead of warning when the config names channels the export lacks
+    #[clap(long)]
+    strict_config: bool,
+    /// Fail instead of warning on duplicate user ids or users without a name
+    #[clap(long)]
+    strict_users: bool,
+    /// Put before the Slack name of every Discord channel created or looked up, like `slack-`
+    #[clap(long, default_value = "")]
+    channel_prefix: String,
+    /// Put after the Slack name of every Discord channel created or looked up
+    #[clap(long, default_value = "")]
+    channel_suffix: String,
+    /// Put before the name of every Discord category created or looked up, after any rename
+    /// from `category_names` in the config
+    #[clap(long, default_value = "")]
+    category_prefix: String,
+    /// Never adopt an existing category of the same name, only ones earlier runs created.
+    /// Combine with --category-prefix to keep the names apart as well
+    #[clap(long)]
+    dedicated_categories: bool,
+    /// Move the migrated categories next to each other in name order, the first to this
+    /// position in the channel list: 0 puts them on top, a large number at the bottom.
+    /// Discord categories cannot nest, so this is how they are kept apart from the guild's own
+    #[clap(long, value_name = "POSITION")]
+    category_position: Option<u32>,
+    /// Leave this channel out even if the config maps it, can be repeated
+    #[clap(long)]
+    exclude_channel: Vec<String>,
+    /// Start each newly created channel with a message listing its Slack members
+    #[clap(long)]
+    post_member_roster: bool,
+    /// Start each newly created channel with a pinned message naming the Slack channel it
+    /// comes from, its date range and message count
+    #[clap(long)]
+    post_intro: bool,
+    /// Post replies also sent to the channel to `both` the thread and channel, or `channel` only
+    #[clap(long, default_value = "both")]
+    thread_broadcast: slack_to_discord::BroadcastMode,
+    /// Post thread replies into the channel, each after a reference to its parent
+    #[clap(long)]
+    threads_as_replies_inline: bool,
+    /// Fail before posting anything if a message mentions a user missing from the export
+    #[clap(long)]
+    strict_format: bool,
+    /// Pin messages that were pinned in Slack
+    #[clap(long)]
+    pin_pinned: bool,
+    /// Prefix starred messages with ⭐
+    #[clap(long)]
+    star_prefix: bool,
+    /// Render each message with this template instead of the built-in header, see
+    /// `MessageTemplate` for the placeholders
+    #[clap(long)]
+    message_template_file: Option<PathBuf>,
+    /// Workspace URL, like https://example.slack.com, for `{{permalink}}` in a template
+    #[clap(long)]
+    slack_workspace_url: Option<String>,
+    /// Drop repeats of a user's previous message within this many seconds
+    #[clap(long)]
+    dedup_window: Option<u64>,
+    /// Leave out the `**user** date` header of a message posted within this many seconds of
+    /// the previous one by the same user, in the channel or thread. Discord itself groups
+    /// messages within 420
+    #[clap(long)]
+    group_consecutive: Option<u64>,
+    /// Dump each channel as parsed, download tokens stripped, into this directory for bug reports
+    #[clap(long)]
+    attach_json: Option<PathBuf>,
+    /// JSON file recording the last posted message of each channel and the counts so far,
+    /// rewritten after every channel
+    #[clap(long)]
+    state_file: Option<PathBuf>,
+    /// Append the reactions of each message as a `Reactions: 👍 x3` line
+    #[clap(long)]
+    include_reactions_as_text: bool,
+    /// Stop starting new messages once the run has taken this long, like 90m. Rerun to
+    /// resume where it stopped
+    #[clap(long)]
+    max_runtime: Option<HumanDuration>,
+    /// Post channel messages and thread parents but no replies. A later run with
+    /// --only-threads on the same database fills the threads in
+    #[clap(long, conflicts_with = "only-threads")]
+    skip_threads: bool,
+    /// Post only thread replies, into threads an earlier --skip-threads run posted
+    #[clap(long)]
+    only_threads: bool,
+    /// Post messages without downloading their files, remembering which had some. A later
+    /// run with --only-files on the same database posts those files after their messages
+    #[clap(long, conflicts_with = "only-files")]
+    skip_files: bool,
+    /// Post only messages with files: the files an earlier --skip-files run left out, as
+    /// follow-ups to their messages, and messages not posted yet in full. Messages already
+    /// posted are never posted again
+    #[clap(long)]
+    only_files: bool,
+    /// Render Slack block kit content instead of the plain text of messages that have both.
+    /// Messages with empty text use their blocks either way
+    #[clap(long)]
+    prefer_blocks: bool,
+    /// Leave out the messages Slack posts itself, like joins and topic changes. Replies in
+    /// their threads are then treated like those of any thread never posted
+    #[clap(long)]
+    skip_system_messages: bool,
+    /// Times a message whose attachments fail to upload is sent again before it is posted
+    /// without them. All the files of the message are left out then, not only the failing one
+    #[clap(long, default_value = "2")]
+    file_retries: u32,
+    /// Discord API version to call, for when the default one is retired
+    #[clap(long, default_value = slack_to_discord::discord::DISCORD_API_VERSION)]
+    discord_api_version: String,
+    /// POST a JSON summary of the run here when it finishes or fails
+    #[clap(long)]
+    notify_webhook: Option<String>,
+    /// Threads of a channel to post concurrently, keeping the order within each thread
+    #[clap(long, default_value = "1")]
+    workers_per_channel: usize,
+    /// Append the reply count and repliers to each thread parent
+    #[clap(long)]
+    thread_summary: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Inspect and shrink the database
+    Maintenance {
+        #[clap(short, long)]
+        db: String,
+        /// Delete every cached file but keep the record of posted messages
+        #[clap(long)]
+        clear_cache: bool,
+        /// Give freed space back to the filesystem
+        #[clap(long)]
+        vacuum: bool,
+    },
+    /// Delete the Discord messages recorded in the database, then their records. Needs
+    /// BOT_TOKEN
+    Rollback {
+        #[clap(short, long)]
+        db: String,
+        /// Only the messages of this Slack channel id, instead of every recorded one
+        #[clap(long)]
+        channel: Option<String>,
+    },
+    /// Compare an export with the posts recorded in the database, opened read-only
+    Verify {
+        #[clap(short, long)]
+        db: String,
+        #[clap(short, long)]
+        msg: PathBuf,
+        /// Also check group DMs and DMs
+        #[clap(long)]
+        include_dms: bool,
+        /// Also check private channels
+        #[clap(long)]
+        include_private: bool,
+    },
+    /// Download every file of an export into the database cache without posting anything
+    Prefetch {
+        #[clap(short, long)]
+        db: String,
+        #[clap(short, long)]
+        msg: PathBuf,
+        /// Also fetch files of group DMs and DMs
+        #[clap(long)]
+        include_dms: bool,
+        /// Also fetch files of private channels
+        #[clap(long)]
+        include_private: bool,
+        /// Downloads run at once
+        #[clap(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+    /// Check that a migration could run: the database, BOT_TOKEN, the bot's membership and
+    /// permissions in GUILD_ID, and the export. Fails if any check does
+    Doctor {
+        #[clap(short, long)]
+        db: String,
+        #[clap(short, long)]
+        msg: PathBuf,
+    },
+    /// Print a config mapping every channel of the export to one category
+    PrintConfigTemplate {
+        #[clap(short, long)]
+        msg: PathBuf,
+        /// Category every channel is mapped to
+        #[clap(long, default_value = "Slack")]
+        category: String,
+        /// `json`, `toml` or `yaml`, read back by --config files of the same extension
+        #[clap(long, default_value = "json")]
+        format: ConfigFormat,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 struct Config {
     channel: ChannelConfig,
+    /// Prefix messages with `**user** date`, on unless set
+    include_header: Option<bool>,
+    /// Per-channel overrides of `include_header`
+    #[serde(default)]
+    include_header_channels: HashMap<String, bool>,
+    /// Category for channels archived on Slack, instead of the one in `channel`
+    archived_category: Option<String>,
+    /// `realname`, `username` or `both`, real names unless set
+    name_style: Option<slack::NameStyle>,
+    /// strftime pattern for message dates, RFC 2822 unless set
+    date_format: Option<String>,
+    /// Largest side in pixels of thumbnails posted for images over the attachment limit,
+    /// which are skipped unless set
+    image_downscale: Option<u32>,
+    /// Keep Discord from unfurling messages that are only a link, which it does unless set
+    suppress_url_embeds: Option<bool>,
+    /// Post every channel as a thread of this Discord text channel instead of a channel of
+    /// its own. Replies are then posted inline, as threads cannot hold threads
+    hub_channel: Option<String>,
+    /// Discord names of the categories in `channel`, for ones named differently on Discord
+    #[serde(default)]
+    category_names: HashMap<String, String>,
+    /// Slack channels created as Discord forums. Every top-level message starts a post there,
+    /// named after its first line, and its replies are posted in it
+    #[serde(default)]
+    forum_channels: BTreeSet<String>,
+    /// Tags created with the forums, each given to posts whose first line matches its pattern
+    #[serde(default)]
+    forum_tags: Vec<slack_to_discord::ForumTagRule>,
+}
+
+impl Config {
+    /// `base` with the settings of this config and the header setting of `channel` applied.
+    fn post_options(
+        &self,
+        base: &slack_to_discord::PostOptions,
+        channel: &str,
+    ) -> slack_to_discord::PostOptions {
+        slack_to_discord::PostOptions {
+            include_header: self
+                .include_header_channels
+                .get(channel)
+                .copied()
+                .or(self.include_header)
+                .unwrap_or(base.include_header),
+            name_style: self.name_style.unwrap_or(base.name_style),
+            date_format: self
+                .date_format
+                .clone()
+                .or_else(|| base.date_format.clone()),
+            image_downscale: self.image_downscale.or(base.image_downscale),
+            suppress_url_embeds: self.suppress_url_embeds.unwrap_or(base.suppress_url_embeds),
+            // hub channels are threads already, which cannot hold threads
+            inline_threads: base.inline_threads || self.hub_channel.is_some(),
+            ..base.clone()
+        }
+    }
+}
+
+static ENV_REFERENCE: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+/// Replace every `${VAR}` in the strings and keys of the parsed config `value` by the variable,
+/// so the value needs no escaping in whichever format the config is written. Fails naming
+/// every variable that is unset.
+fn expand_env(value: &mut serde_json::Value) -> Result<(), anyhow::Error> {
+    fn expand_str(s: &str, unset: &mut BTreeSet<String>) -> String {
+        ENV_REFERENCE
+            .replace_all(s, |caps: &regex::Captures| {
+                std::env::var(&caps[1]).unwrap_or_else(|_| {
+                    unset.insert(caps[1].to_owned());
+                    String::new()
+                })
+            })
+            .into_owned()
+    }
+    fn expand(value: &mut serde_json::Value, unset: &mut BTreeSet<String>) {
+        match value {
+            serde_json::Value::String(s) => *s = expand_str(s, unset),
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    expand(value, unset);
+                }
+            }
+            serde_json::Value::Object(values) => {
+                *values = std::mem::take(values)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        expand(&mut value, unset);
+                        (expand_str(&key, unset), value)
+                    })
+                    .collect();
+            }
+            _ => (),
+        }
+    }
+    let mut unset = BTreeSet::new();
+    expand(value, &mut unset);
+    if !unset.is_empty() {
+        anyhow::bail!(
+            "config refers to unset environment variables: {}",
+            unset.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Warn about, or with `strict` fail on, channels the config names that the export lacks.
+fn check_config_channels(
+    config: &Config,
+    export_channels: &HashSet<String>,
+    strict: bool,
+) -> Result<(), anyhow::Error> {
+    let unknown = config
+        .channel
+        .channel_names()
+        .chain(config.include_header_channels.keys().map(String::as_str))
+        .filter(|name| !export_channels.contains(*name))
+        .collect::<BTreeSet<_>>();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "{} channels in the config are not in the export: {}",
+        unknown.len(),
+        unknown.into_iter().collect::<Vec<_>>().join(", ")
+    );
+    if strict {
+        anyhow::bail!(message);
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+fn report_failed_files(channel: &str, report: &slack_to_discord::ChannelReport) {
+    for failed in &report.failed_files {
+        warn!(
+            "channel {}: file {} ({}) of {} was not attached: {}",
+            channel, failed.title, failed.url, failed.ts, failed.error
+        );
+    }
+}
+
+#[derive(Default)]
+struct Summary {
+    channels: usize,
+    messages: usize,
+    failed_files: usize,
+    failed_channels: Vec<(String, anyhow::Error)>,
+    /// `--max-runtime` ran out before every channel was posted.
+    out_of_time: bool,
+    /// Messages Discord timestamped earlier than the one posted before them.
+    out_of_order: usize,
+    /// Bytes of files attached and of files left out as over the limit, by Slack filetype.
+    attached_bytes: BTreeMap<String, u64>,
+    oversized_bytes: BTreeMap<String, u64>,
+}
+
+impl Summary {
+    fn record(
+        &mut self,
+        channel: &str,
+        result: Result<slack_to_discord::ChannelReport, anyhow::Error>,
+        continue_on_error: bool,
+    ) -> Result<(), anyhow::Error> {
+        match result {
+            Ok(report) => {
+                report_failed_files(channel, &report);
+                if report.collapsed > 0 {
+                    info!(
+                        "channel {}: collapsed {} repeated messages",
+                        channel, report.collapsed
+                    );
+                }
+                if !report.skipped_replies.is_empty() {
+                    warn!(
+                        "channel {}: skipped {} replies to threads never posted",
+                        channel,
+                        report.skipped_replies.len()
+                    );
+                }
+                if !report.out_of_order.is_empty() {
+                    warn!(
+                        "channel {}: {} messages landed out of order: {}",
+                        channel,
+                        report.out_of_order.len(),
+                        report
+                            .out_of_order
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
+                self.channels += 1;
+                self.messages += report.posted;
+                self.out_of_order += report.out_of_order.len();
+                self.out_of_time |= report.out_of_time;
+                self.failed_files += report.failed_files.len();
+                for (filetype, bytes) in report.attached_bytes {
+                    *self.attached_bytes.entry(filetype).or_default() += bytes;
+                }
+                for (filetype, bytes) in report.oversized_bytes {
+                    *self.oversized_bytes.entry(filetype).or_default() += bytes;
+                }
+                Ok(())
+            }
+            Err(e) if continue_on_error && !slack_to_discord::PostError::is_unavailable(&e) => {
+                error!("channel {} failed, continue: {:#}", channel, e);
+                self.failed_channels.push((channel.to_owned(), e));
+                Ok(())
+            }
+            Err(e) => Err(e.context(format!("channel {}", channel))),
+        }
+    }
+
+    fn finish(&self) -> Result<(), anyhow::Error> {
+        info!("migrated {} channels", self.channels);
+        for (filetype, bytes) in &self.attached_bytes {
+            info!("attached {} bytes of {}", bytes, filetype);
+        }
+        for (filetype, bytes) in &self.oversized_bytes {
+            warn!(
+                "left out {} bytes of {} over the attachment limit",
+                bytes, filetype
+            );
+        }
+        if self.out_of_time {
+            warn!("stopped at the --max-runtime budget, rerun to resume");
+        }
+        if self.failed_channels.is_empty() {
+            return Ok(());
+        }
+        for (channel, e) in &self.failed_channels {
+            error!("channel {} failed: {:#}", channel, e);
+        }
+        Err(anyhow::anyhow!(
+            "{} channels failed: {}",
+            self.failed_channels.len(),
+            self.failed_channels
+                .iter()
+                .map(|(channel, _)| channel.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// The `--state-file` checkpoint agreeing with `db`.
+async fn load_checkpoint(
+    path: Option<PathBuf>,
+    db: &slack_to_discord::Db,
+) -> Result<Option<(PathBuf, Checkpoint)>, anyhow::Error> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let mut checkpoint = Checkpoint::load(&path)?;
+    checkpoint.reconcile(db).await?;
+    checkpoint.save(&path)?;
+    Ok(Some((path, checkpoint)))
+}
+
+async fn save_checkpoint(
+    state: &mut Option<(PathBuf, Checkpoint)>,
+    db: &slack_to_discord::Db,
+    channel: &slack_to_discord::SlackChannel,
+    result: &Result<slack_to_discord::ChannelReport, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    if let (Some((path, checkpoint)), Ok(report)) = (state, result) {
+        checkpoint.record(db, channel, report).await?;
+        checkpoint.save(path)?;
+    }
+    Ok(())
+}
+
+async fn print_db_size(db: &slack_to_discord::Db) -> Result<(), anyhow::Error> {
+    println!(
+        "database: {} bytes, file cache: {} bytes",
+        db.size_bytes().await?,
+        db.cache_size_bytes().await?
+    );
+    Ok(())
+}
+
+async fn maintenance(db: &str, clear_cache: bool, vacuum: bool) -> Result<(), anyhow::Error> {
+    let db = slack_to_discord::Db::new(db).await?;
+    match db.schema_version().await? {
+        Some(version) => println!("schema version: {}", version),
+        None => println!("schema version: unrecorded"),
+    }
+    print_db_size(&db).await?;
+    if clear_cache {
+        let removed = db.clear_cache().await.with_context(|| "clear file cache")?;
+        println!("removed {} cached files", removed);
+    }
+    if vacuum {
+        db.vacuum().await.with_context(|| "vacuum db")?;
+        print_db_size(&db).await?;
+    }
+    Ok(())
+}
+
+/// Log lines printed above the bars of `multi`, which are cleared and redrawn around each.
+fn init_logging_above(format: LogFormat, multi: &MultiProgress) {
+    #[derive(Clone)]
+    struct AboveBars(MultiProgress);
+
+    impl io::Write for AboveBars {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.suspend(|| io::stdout().write(buf))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            io::stdout().flush()
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for AboveBars {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    // like `fmt::init`, levels come from RUST_LOG and default to info
+    let targets = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|var| var.parse::<Targets>().ok())
+        .unwrap_or_else(|| Targets::new().with_default(tracing::Level::INFO));
+    let writer = AboveBars(multi.clone());
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => builder.finish().with(targets).init(),
+        LogFormat::Json => builder.json().finish().with(targets).init(),
+    }
+}
+
+/// `--progress` bars: messages of the whole run, and of the channel being posted.
+struct ProgressBars {
+    multi: MultiProgress,
+    overall: ProgressBar,
+}
+
+impl ProgressBars {
+    fn new(multi: MultiProgress, channels: &[slack_to_discord::SlackChannel]) -> Self {
+        let total = channels
+            .iter()
+            .map(|channel| channel.messages.len() as u64)
+            .sum();
+        let overall = multi.add(ProgressBar::new(total));
+        overall.set_style(
+            ProgressStyle::with_template("{prefix:>20} [{bar:40}] {pos}/{len} messages, {eta}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        overall.set_prefix("all channels");
+        Self { multi, overall }
+    }
+
+    /// `options` reporting to a new bar for `channel`, and the bar.
+    fn channel(
+        &self,
+        channel: &slack_to_discord::SlackChannel,
+        mut options: slack_to_discord::PostOptions,
+    ) -> (slack_to_discord::PostOptions, Option<ProgressBar>) {
+        let bar = self
+            .multi
+            .add(ProgressBar::new(channel.messages.len() as u64));
+        bar.set_style(
+            ProgressStyle::with_template("{prefix:>20} [{bar:40}] {pos}/{len}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(format!("#{}", channel.name));
+        let (channel_bar, overall) = (bar.clone(), self.overall.clone());
+        options.on_progress = Some(std::sync::Arc::new(move |progress| {
+            channel_bar.set_length(progress.total as u64);
+            channel_bar.set_position(progress.handled as u64);
+            overall.inc(1);
+        }));
+        (options, Some(bar))
+    }
+
+    /// Drop the bar of `channel` and count the messages it left out as done in the total.
+    fn finish_channel(&self, channel: &slack_to_discord::SlackChannel, bar: Option<ProgressBar>) {
+        if let Some(bar) = bar {
+            let skipped = (channel.messages.len() as u64).saturating_sub(bar.position());
+            self.overall.inc(skipped);
+            bar.finish_and_clear();
+            self.multi.remove(&bar);
+        }
+    }
+}
+
+/// Where the bot token and guild id are read from. Their values never go into errors or logs.
+struct Secrets {
+    token_file: Option<PathBuf>,
+    token_stdin: bool,
+    guild_id_file: Option<PathBuf>,
+}
+
+impl Secrets {
+    fn new(opts: &Opts) -> Self {
+        Self {
+            token_file: opts.token_file.clone(),
+            token_stdin: opts.token_stdin,
+            guild_id_file: opts.guild_id_file.clone(),
+        }
+    }
+
+    /// Read once, stdin only has the token the first time.
+    fn bot_token(&self) -> Result<slack_to_discord::discord::BotToken, anyhow::Error> {
+        let token = match (&self.token_file, self.token_stdin) {
+            (Some(path), _) => read_secret_file(path)?,
+            (None, true) => {
+                let mut line = String::new();
+                io::stdin()
+                    .read_line(&mut line)
+                    .with_context(|| "read bot token from stdin")?;
+                let line = line.trim();
+                if line.is_empty() {
+                    anyhow::bail!("no bot token on stdin");
+                }
+                line.to_owned()
+            }
+            (None, false) => {
+                return slack_to_discord::discord::BotToken::from_env("BOT_TOKEN")
+                    .with_context(|| "BOT_TOKEN")
+            }
+        };
+        Ok(slack_to_discord::discord::BotToken::new(token))
+    }
+
+    fn guild_id(&self) -> Result<slack_to_discord::discord::GuildId, anyhow::Error> {
+        match &self.guild_id_file {
+            Some(path) => Ok(slack_to_discord::discord::GuildId::new(read_secret_file(
+                path,
+            )?)),
+            None => {
+                slack_to_discord::discord::GuildId::from_env("GUILD_ID").with_context(|| "GUILD_ID")
+            }
+        }
+    }
+}
+
+/// The content of `path` without surrounding whitespace, failing if nothing is left.
+fn read_secret_file(path: &Path) -> Result<String, anyhow::Error> {
+    let secret = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let secret = secret.trim();
+    if secret.is_empty() {
+        anyhow::bail!("{} is empty", path.display());
+    }
+    Ok(secret.to_owned())
+}
+
+async fn rollback(db: &str, channel: Option<&str>, secrets: &Secrets) -> Result<(), anyhow::Error> {
+    let db = slack_to_discord::Db::new(db).await?;
+    let token = secrets.bot_token()?;
+    let client = slack_to_discord::discord::DiscordClient::new(token, Default::default());
+    let report = slack_to_discord::rollback(&db, &client, channel).await?;
+    println!(
+        "deleted {} threads and {} messages, {} were already gone",
+        report.threads, report.messages, report.missing
+    );
+    Ok(())
+}
+
+async fn verify(
+    db: &str,
+    msg: &Path,
+    options: &slack_to_discord::LoadOptions,
+) -> Result<(), anyhow::Error> {
+    let db = slack_to_discord::Db::new_with_options(
+        db,
+        &slack_to_discord::DbOptions {
+            read_only: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut source = ExportSource::new(slack_to_discord::archive::open(msg)?);
+    let users = users_by_id(source.users().await?, false)?;
+    let mut channels = source
+        .channels(&users, options)
+        .await
+        .with_context(|| "load messages")?;
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    report_posted(&db, &channels).await
+}
+
+/// Print posted/total messages of every channel, failing if any channel is incomplete.
+async fn report_posted(
+    db: &slack_to_discord::Db,
+    channels: &[slack_to_discord::SlackChannel],
+) -> Result<(), anyhow::Error> {
+    let mut incomplete = Vec::new();
+    for channel in channels {
+        let posted = db.posted_count(&channel.id).await?;
+        println!("{}: {}/{}", channel.name, posted, channel.messages.len());
+        if posted < channel.messages.len() as i64 {
+            incomplete.push(channel.name.as_str());
+        }
+    }
+    if !incomplete.is_empty() {
+        anyhow::bail!(
+            "{} channels are incomplete: {}",
+            incomplete.len(),
+            incomplete.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Run every check of `doctor` and print how each went, failing if any did.
+async fn doctor(db: &str, msg: &Path, secrets: &Secrets) -> Result<(), anyhow::Error> {
+    let mut failed = 0;
+    let mut report = |check: &str, result: Result<String, anyhow::Error>| match result {
+        Ok(detail) => println!("ok   {}: {}", check, detail),
+        Err(e) => {
+            println!("FAIL {}: {:#}", check, e);
+            failed += 1;
+        }
+    };
+
+    let schema = async {
+        let db = slack_to_discord::Db::new_with_options(
+            db,
+            &slack_to_discord::DbOptions {
+                read_only: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+        match db.schema_version().await? {
+            Some(version) if version == slack_to_discord::SCHEMA_VERSION => {
+                Ok(format!("schema {}", version))
+            }
+            Some(version) => Err(anyhow::anyhow!(
+                "schema {} is older than {}, apply the migrations",
+                version,
+                slack_to_discord::SCHEMA_VERSION
+            )),
+            None => Err(anyhow::anyhow!("no schema recorded, apply the migrations")),
+        }
+    };
+    report("database", schema.await);
+
+    let client = secrets
+        .bot_token()
+        .map(|token| slack_to_discord::discord::DiscordClient::new(token, Default::default()));
+    let user = match &client {
+        Ok(client) => client
+            .get_current_user()
+            .await
+            .with_context(|| "get the bot user"),
+        Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+    };
+    report(
+        "token",
+        user.as_ref()
+            .map(|user| format!("bot {} ({})", user.username, user.id))
+            .map_err(|e| anyhow::anyhow!("{:#}", e)),
+    );
+
+    let guild = secrets.guild_id();
+    match (&client, &user, guild) {
+        (Ok(client), Ok(user), Ok(guild)) => {
+            let info = client
+                .get_guild(&guild)
+                .await
+                .with_context(|| "the bot is not a member of it, or the id is wrong");
+            let joined = info.is_ok();
+            report(
+                "guild",
+                info.map(|info| format!("{} ({})", info.name, info.id)),
+            );
+            let (permissions, hidden) = if joined {
+                (
+                    check_permissions(client, &guild, &user.id).await,
+                    check_hidden_channels(client, &guild, &user.id).await,
+                )
+            } else {
+                (
+                    Err(anyhow::anyhow!("needs the guild")),
+                    Err(anyhow::anyhow!("needs the guild")),
+                )
+            };
+            report("permissions", permissions);
+            report("hidden channels", hidden);
+        }
+        (_, _, Err(e)) => {
+            report("guild", Err(e));
+            report("permissions", Err(anyhow::anyhow!("needs the guild")));
+            report("hidden channels", Err(anyhow::anyhow!("needs the guild")));
+        }
+        _ => {
+            report("guild", Err(anyhow::anyhow!("needs a valid token")));
+            report("permissions", Err(anyhow::anyhow!("needs a valid token")));
+            report(
+                "hidden channels",
+                Err(anyhow::anyhow!("needs a valid token")),
+            );
+        }
+    }
+    report("export", check_export(msg).await);
+
+    if failed > 0 {
+        anyhow::bail!("{} checks failed", failed);
+    }
+    Ok(())
+}
+
+/// Guild-wide permissions the migration needs that the bot lacks. Channel overwrites are
+/// not looked at, they could still take some away.
+async fn check_permissions(
+    client: &slack_to_discord::discord::DiscordClient,
+    guild: &slack_to_discord::discord::GuildId,
+    user: &str,
+) -> Result<String, anyhow::Error> {
+    let permissions = client.guild_permissions(guild, user).await?;
+    let missing = slack_to_discord::discord::MIGRATION_PERMISSIONS
+        .iter()
+        .filter(|(_, bit)| permissions & bit == 0)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        anyhow::bail!("missing {}", missing.join(", "));
+    }
+    Ok(format!(
+        "all {} granted",
+        slack_to_discord::discord::MIGRATION_PERMISSIONS.len()
+    ))
+}
+
+/// Channels hidden from `@everyone` that do not let the bot back in. Administrators see
+/// every channel regardless.
+async fn check_hidden_channels(
+    client: &slack_to_discord::discord::DiscordClient,
+    guild: &slack_to_discord::discord::GuildId,
+    user: &str,
+) -> Result<String, anyhow::Error> {
+    let permissions = client.guild_permissions(guild, user).await?;
+    if permissions & slack_to_discord::discord::PERMISSION_ADMINISTRATOR != 0 {
+        return Ok("the bot is an administrator".to_owned());
+    }
+    let everyone = guild.everyone_role();
+    let channels = client.get_channels(guild).await?;
+    let hidden = channels
+        .iter()
+        .filter(|channel| {
+            channel
+                .permission_overwrites
+                .iter()
+                .any(|overwrite| overwrite.hides_from_role(&everyone))
+        })
+        .collect::<Vec<_>>();
+    let locked_out = hidden
+        .iter()
+        .filter(|channel| {
+            !channel
+                .permission_overwrites
+                .iter()
+                .any(|overwrite| overwrite.lets_member_view(user))
+        })
+        .map(|channel| format!("#{}", channel.name))
+        .collect::<Vec<_>>();
+    if !locked_out.is_empty() {
+        anyhow::bail!("the bot cannot see {}", locked_out.join(", "));
+    }
+    Ok(format!("the bot sees all {}", hidden.len()))
+}
+
+/// Load the whole export at `msg` the way a migration would.
+async fn check_export(msg: &Path) -> Result<String, anyhow::Error> {
+    let mut source = ExportSource::new(slack_to_discord::archive::open(msg)?);
+    let users = users_by_id(source.users().await?, false)?;
+    let channels = source
+        .channels(
+            &users,
+            &slack_to_discord::LoadOptions {
+                include_dms: true,
+                include_private: true,
+            },
+        )
+        .await?;
+    Ok(format!(
+        "{} users, {} channels, {} messages",
+        users.len(),
+        channels.len(),
+        channels
+            .iter()
+            .map(|channel| channel.messages.len())
+            .sum::<usize>()
+    ))
+}
+
+async fn prefetch(
+    db: &str,
+    msg: &Path,
+    options: &slack_to_discord::LoadOptions,
+    concurrency: usize,
+) -> Result<(), anyhow::Error> {
+    let db = slack_to_discord::Db::new_with_options(db, &Default::default()).await?;
+    let mut source = ExportSource::new(slack_to_discord::archive::open(msg)?);
+    let users = users_by_id(source.users().await?, false)?;
+    let channels = source
+        .channels(&users, options)
+        .await
+        .with_context(|| "load messages")?;
+    let report = slack_to_discord::prefetch_files(&db, &channels, concurrency).await;
+    println!(
+        "{} cached, {} downloaded, {} failed",
+        report.cached,
+        report.downloaded,
+        report.failed_files.len()
+    );
+    if !report.failed_files.is_empty() {
+        anyhow::bail!("{} files failed to download", report.failed_files.len());
+    }
+    Ok(())
+}
+
+fn print_config_template(
+    msg: &Path,
+    category: &str,
+    format: ConfigFormat,
+) -> Result<(), anyhow::Error> {
+    let mut archive = slack_to_discord::archive::open(msg)?;
+    let channels = match archive.open("channels.json")? {
+        Some(channels) => Some(
+            serde_json::from_reader::<_, Vec<slack::Channel>>(channels)
+                .with_context(|| "parse channels.json")?,
+        ),
+        None => None,
+    };
+    let names = match channels {
+        Some(channels) => channels.into_iter().map(|channel| channel.name).collect(),
+        None => {
+            // partial exports, which the migration names by directory as well
+            warn!("channels.json not found, channels are taken from the directories");
+            slack_to_discord::channel_dirs(&mut archive)?
+        }
+    };
+    let config = Config {
+        channel: names
+            .into_iter()
+            .map(|name| (name, category.to_owned()))
+            .collect(),
+        include_header: Some(true),
+        include_header_channels: HashMap::new(),
+        archived_category: None,
+        name_style: Some(slack::NameStyle::RealName),
+        date_format: None,
+        image_downscale: None,
+        suppress_url_embeds: None,
+        hub_channel: None,
+        category_names: HashMap::new(),
+        forum_channels: BTreeSet::new(),
+        forum_tags: Vec::new(),
+    };
+    println!("{}", format.print(&config)?);
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    tracing_subscriber::fmt::init();
-
     let opts = Opts::parse();
 
-    let db = slack_to_discord::Db::new(&opts.db).await?;
-
-    let archive = fs::File::open(opts.msg).with_context(|| "Reading msg archive")?;
-    let archive = io::BufReader::new(archive);
-    let mut archive = zip::ZipArchive::new(archive).with_context(|| "Open msg archive")?;
+    let progress = (opts.progress && opts.command.is_none()).then(MultiProgress::new);
+    match (opts.log_format, &progress) {
+        (LogFormat::Text, None) => tracing_subscriber::fmt::init(),
+        (LogFormat::Json, None) => tracing_subscriber::fmt().json().init(),
+        (format, Some(multi)) => init_logging_above(format, multi),
+    }
 
-    let channels_json = archive
-        .by_name("channels.json")
-        .with_context(|| "read channels.json")?;
-    let channels = serde_json::from_reader(channels_json).with_context(|| "parse channels.json")?;
+    let secrets = Secrets::new(&opts);
+    match &opts.command {
+        Some(Command::Maintenance {
+            db,
+            clear_cache,
+            vacuum,
+        }) => maintenance(db, *clear_cache, *vacuum).await,
+        Some(Command::Rollback { db, channel }) => rollback(db, channel.as_deref(), &secrets).await,
+        Some(Command::Verify {
+            db,
+            msg,
+            include_dms,
+            include_private,
+        }) => {
+            verify(
+                db,
+                msg,
+                &slack_to_discord::LoadOptions {
+                    include_dms: *include_dms,
+                    include_private: *include_private,
+                },
+            )
+            .await
+        }
+        Some(Command::Prefetch {
+            db,
+            msg,
+            include_dms,
+            include_private,
+            concurrency,
+        }) => {
+            prefetch(
+                db,
+                msg,
+                &slack_to_discord::LoadOptions {
+                    include_dms: *include_dms,
+                    include_private: *include_private,
+                },
+                *concurrency,
+            )
+            .await
+        }
+        Some(Command::Doctor { db, msg }) => doctor(db, msg, &secrets).await,
+        Some(Command::PrintConfigTemplate {
+            msg,
+            category,
+            format,
+        }) => print_config_template(msg, category, *format),
+        None => {
+            let webhook = opts.notify_webhook.clone();
+            let started = std::time::Instant::now();
+            let mut summary = Summary::default();
+            let result = migrate(opts, &secrets, progress, &mut summary).await;
+            if let Some(url) = webhook {
+                notify_webhook(&url, &summary, &result, started.elapsed()).await;
+            }
+            result
+        }
+    }
+}
 
-    let users = archive
-        .by_name("users.json")
-        .with_context(|| "read users.json")?;
-    let users = serde_json::from_reader::<_, Vec<slack::User>>(users)
-        .with_context(|| "parse users.json")?
-        .into_iter()
-        .map(|user| (user.id.clone(), user))
-        .collect::<HashMap<_, _>>();
+/// POST the outcome of a migration to `url` as JSON. `content` and `text` carry a one-line
+/// report, so Discord and Slack incoming webhooks can take it as is.
+async fn notify_webhook(
+    url: &str,
+    summary: &Summary,
+    result: &Result<(), anyhow::Error>,
+    duration: Duration,
+) {
+    let line = match result {
+        Ok(()) if summary.out_of_time => format!(
+            "migration stopped at its time budget: {} channels, {} messages in {}s",
+            summary.channels,
+            summary.messages,
+            duration.as_secs()
+        ),
+        Ok(()) => format!(
+            "migration finished: {} channels, {} messages in {}s",
+            summary.channels,
+            summary.messages,
+            duration.as_secs()
+        ),
+        Err(e) => format!(
+            "migration failed after {} channels, {} messages: {:#}",
+            summary.channels, summary.messages, e
+        ),
+    };
+    let payload = serde_json::json!({
+        "content": line,
+        "text": line,
+        "succeeded": result.is_ok(),
+        "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+        "channels": summary.channels,
+        "messages": summary.messages,
+        "failed_files": summary.failed_files,
+        "out_of_time": summary.out_of_time,
+        "out_of_order": summary.out_of_order,
+        "attached_bytes": summary.attached_bytes,
+        "oversized_bytes": summary.oversized_bytes,
+        "failed_channels": summary
+            .failed_channels
+            .iter()
+            .map(|(channel, e)| serde_json::json!({ "channel": channel, "error": format!("{:#}", e) }))
+            .collect::<Vec<_>>(),
+        "duration_secs": duration.as_secs_f64(),
+    });
+    match reqwest::Client::new().post(url).json(&payload).send().await {
+        Ok(response) if response.status().is_success() => info!("notified {}", url),
+        Ok(response) => warn!("notify {} failed with {}", url, response.status()),
+        Err(e) => warn!("notify {} failed: {}", url, e),
+    }
+}
 
-    let guild = slack_to_discord::discord::GuildId::from_env("GUILD_ID")?;
-    let token = slack_to_discord::discord::BotToken::from_env("BOT_TOKEN")?;
+async fn migrate(
+    opts: Opts,
+    secrets: &Secrets,
+    progress: Option<MultiProgress>,
+    summary: &mut Summary,
+) -> Result<(), anyhow::Error> {
+    let deadline = opts
+        .max_runtime
+        .map(|max_runtime| std::time::Instant::now() + max_runtime.0);
+    let mut source: Box<dyn SlackSource> = if opts.from_api {
+        let token = slack_to_discord::slack_api::SlackToken::from_env("SLACK_TOKEN")?;
+        Box::new(ApiSource::new(
+            slack_to_discord::slack_api::SlackApiClient::new(token),
+        ))
+    } else {
+        let msg = opts.msg.as_ref().with_context(|| "--msg is required")?;
+        Box::new(ExportSource::new(slack_to_discord::archive::open(msg)?))
+    };
 
-    let config = tokio::fs::read(opts.config)
+    let users = users_by_id(source.users().await?, opts.strict_users)?;
+    let mut slack_messages = source
+        .channels(
+            &users,
+            &slack_to_discord::LoadOptions {
+                include_dms: opts.include_dms,
+                include_private: opts.include_private,
+            },
+        )
         .await
-        .with_context(|| "read channel config")?;
-    let config: Config = serde_json::from_slice(&config).with_context(|| "parse channel config")?;
+        .with_context(|| "load messages")?;
+    slack_messages.sort_by(|a, b| a.name.cmp(&b.name));
+    let export_channels = slack_messages
+        .iter()
+        .map(|channel| channel.name.clone())
+        .collect::<HashSet<_>>();
+    for excluded in &opts.exclude_channel {
+        if !slack_messages
+            .iter()
+            .any(|channel| &channel.name == excluded)
+        {
+            warn!("--exclude-channel {} is not in the archive", excluded);
+        }
+    }
+    slack_messages.retain(|channel| !opts.exclude_channel.contains(&channel.name));
+    if let Some(dir) = &opts.attach_json {
+        fs::create_dir_all(dir).with_context(|| "create json dump dir")?;
+        for channel in &slack_messages {
+            let path = dir.join(format!("{}.json", channel.name));
+            let file =
+                fs::File::create(&path).with_context(|| format!("create {}", path.display()))?;
+            serde_json::to_writer_pretty(io::BufWriter::new(file), channel)
+                .with_context(|| format!("write {}", path.display()))?;
+        }
+        info!(
+            "dumped {} channels to {}",
+            slack_messages.len(),
+            dir.display()
+        );
+    }
+    let unresolved = slack_to_discord::unresolved_mentions(&slack_messages, &users);
+    if !unresolved.is_empty() {
+        for (user, count) in &unresolved {
+            warn!("unknown user {} is mentioned {} times", user, count);
+        }
+        let message = format!(
+            "{} unknown users are mentioned: {}",
+            unresolved.len(),
+            unresolved.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        if opts.strict_format {
+            anyhow::bail!(message);
+        }
+        warn!("{}", message);
+    }
+    if let Some(start) = &opts.start_channel {
+        if !slack_messages.iter().any(|channel| &channel.name == start) {
+            anyhow::bail!("--start-channel {} is not in the archive", start);
+        }
+        slack_messages.retain(|channel| &channel.name >= start);
+        info!("start from channel {}", start);
+    }
 
-    let discord_channels =
-        slack_to_discord::provision_channels(&guild, &token, channels, &config.channel).await?;
-    let slack_messages =
-        slack_to_discord::get_channels_stream(&mut archive).with_context(|| "load messages")?;
+    let template = match &opts.message_template_file {
+        Some(path) => {
+            let template = fs::read_to_string(path).with_context(|| "read message template")?;
+            Some(
+                slack_to_discord::template::MessageTemplate::parse(&template)
+                    .with_context(|| "parse message template")?,
+            )
+        }
+        None => None,
+    };
+    let post_options = slack_to_discord::PostOptions {
+        broadcast: opts.thread_broadcast,
+        inline_threads: opts.threads_as_replies_inline,
+        pin: opts.pin_pinned,
+        star_prefix: opts.star_prefix,
+        template,
+        workspace_url: opts.slack_workspace_url.clone(),
+        dedup_window: opts.dedup_window.map(Duration::from_secs),
+        group_window: opts.group_consecutive.map(Duration::from_secs),
+        file_retries: opts.file_retries,
+        thread_summary: opts.thread_summary,
+        workers_per_channel: opts.workers_per_channel,
+        continue_on_error: opts.continue_on_error,
+        reactions_as_text: opts.include_reactions_as_text,
+        deadline,
+        failure_budget: Some(std::sync::Arc::new(slack_to_discord::FailureBudget::new(
+            opts.max_consecutive_failures,
+        ))),
+        thread_pass: if opts.skip_threads {
+            slack_to_discord::ThreadPass::SkipReplies
+        } else if opts.only_threads {
+            slack_to_discord::ThreadPass::OnlyReplies
+        } else {
+            slack_to_discord::ThreadPass::All
+        },
+        file_pass: if opts.skip_files {
+            slack_to_discord::FilePass::SkipFiles
+        } else if opts.only_files {
+            slack_to_discord::FilePass::OnlyFiles
+        } else {
+            slack_to_discord::FilePass::All
+        },
+        prefer_blocks: opts.prefer_blocks,
+        skip_system_messages: opts.skip_system_messages,
+        ..Default::default()
+    };
+
+    let db_options = slack_to_discord::DbOptions {
+        busy_timeout: Duration::from_millis(opts.db_busy_timeout_ms),
+        read_only: opts.db_readonly,
+        max_connections: opts.db_connections,
+        revalidate_files: opts.revalidate_files,
+        cache_max_bytes: opts.cache_max_bytes,
+    };
 
+    let config = match &opts.config {
+        Some(path) => {
+            let config = tokio::fs::read(path)
+                .await
+                .with_context(|| "read channel config")?;
+            let config = String::from_utf8(config).with_context(|| "read channel config")?;
+            let config = ConfigFormat::of_path(path)
+                .parse(&config)
+                .with_context(|| "parse channel config")?;
+            if let Some(date_format) = &config.date_format {
+                slack_to_discord::validate_date_format(date_format)?;
+            }
+            for tag in &config.forum_tags {
+                regex::Regex::new(&tag.pattern)
+                    .with_context(|| format!("pattern of forum tag {}", tag.name))?;
+            }
+            check_config_channels(&config, &export_channels, opts.strict_config)?;
+            Some(config)
+        }
+        None => None,
+    };
+
+    let bars = progress.map(|multi| ProgressBars::new(multi, &slack_messages));
+    if let Some(output_dir) = opts.output_dir {
+        let db = slack_to_discord::Db::in_memory().await?;
+        let mut checkpoint = load_checkpoint(opts.state_file, &db).await?;
+        fs::create_dir_all(&output_dir).with_context(|| "create output dir")?;
+        for channel in &slack_messages {
+            let sink = slack_to_discord::markdown::MarkdownSink::new(&output_dir, &channel.name);
+            let options = config
+                .as_ref()
+                .map(|config| config.post_options(&post_options, &channel.name))
+                .unwrap_or_else(|| post_options.clone());
+            let (options, bar) = match &bars {
+                Some(bars) => bars.channel(channel, options),
+                None => (options, None),
+            };
+            let result = async {
+                let report = slack_to_discord::post_channel(
+                    &db,
+                    &sink,
+                    &channel.name,
+                    channel,
+                    &users,
+                    &options,
+                )
+                .await?;
+                sink.write()?;
+                Ok(report)
+            }
+            .await;
+            if let Some(bars) = &bars {
+                bars.finish_channel(channel, bar);
+            }
+            save_checkpoint(&mut checkpoint, &db, channel, &result).await?;
+            summary.record(&channel.name, result, opts.continue_on_error)?;
+            if summary.out_of_time {
+                break;
+            }
+        }
+        return summary.finish();
+    }
+
+    let db = slack_to_discord::Db::new_with_options(
+        &opts.db.with_context(|| "--db is required")?,
+        &db_options,
+    )
+    .await?;
+    if opts.db_readonly {
+        return report_posted(&db, &slack_messages).await;
+    }
+    let mut checkpoint = load_checkpoint(opts.state_file.clone(), &db).await?;
+
+    let guild = secrets.guild_id()?;
+    let token = secrets.bot_token()?;
+    let client = slack_to_discord::discord::DiscordClient::new(
+        token,
+        slack_to_discord::discord::DiscordOptions {
+            global_rate_limit: opts.global_rate_limit,
+            rate_limit_rps: opts.rate_limit_rps,
+            max_attachment_count: opts.max_attachment_count,
+            max_retries: opts.max_retries,
+            api_version: opts.discord_api_version.clone(),
+            denied_mime_types: slack_to_discord::discord::DEFAULT_DENIED_MIME_TYPES
+                .iter()
+                .map(|mime| mime.to_string())
+                .chain(opts.deny_mime)
+                .filter(|mime| {
+                    !opts
+                        .allow_mime
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(mime))
+                })
+                .collect(),
+        },
+    );
+
+    let config = config.with_context(|| "--config is required")?;
+
+    let provision_options = slack_to_discord::ProvisionOptions {
+        roster: opts.post_member_roster.then_some(&users),
+        intro: opts.post_intro,
+        archived_category: config.archived_category.as_deref(),
+        db: Some(&db),
+        channel_prefix: &opts.channel_prefix,
+        channel_suffix: &opts.channel_suffix,
+        category_names: Some(&config.category_names),
+        category_prefix: &opts.category_prefix,
+        dedicated_categories: opts.dedicated_categories,
+        forum_channels: Some(&config.forum_channels),
+        forum_tags: &config.forum_tags,
+        category_position: opts.category_position,
+    };
+    if config.hub_channel.is_some() && !config.forum_channels.is_empty() {
+        anyhow::bail!(
+            "hub_channel and forum_channels cannot be combined, hub threads cannot be forums"
+        );
+    }
+    let discord_channels = match &config.hub_channel {
+        Some(hub) => {
+            slack_to_discord::provision_hub_threads(
+                &client,
+                &guild,
+                &slack_messages,
+                &config.channel,
+                hub,
+                &provision_options,
+            )
+            .await?
+        }
+        None => {
+            slack_to_discord::provision_channels(
+                &client,
+                &guild,
+                &slack_messages,
+                &config.channel,
+                &provision_options,
+            )
+            .await?
+        }
+    };
+
+    let sink = slack_to_discord::sink::DiscordSink::new(&client);
     for channel in slack_messages {
-        if !discord_channels.contains_key(&channel.name) {
+        let Some(discord_channel) = discord_channels.get(&channel.name) else {
             continue;
-        }
+        };
         info!(
             "channel {} has {} messages",
             channel.name,
             channel.messages.len()
         );
-        slack_to_discord::post_channel(&db, &token, &discord_channels, &channel, &users).await?;
+        let mut options = config.post_options(&post_options, &channel.name);
+        // existing forums are posted to as forums, configured or not
+        if discord_channel.channel_type == slack_to_discord::discord::ChannelType::GuildForum {
+            options.forum = Some(slack_to_discord::Forum::new(
+                &config.forum_tags,
+                &discord_channel.available_tags,
+            )?);
+        }
+        let (options, bar) = match &bars {
+            Some(bars) => bars.channel(&channel, options),
+            None => (options, None),
+        };
+        let result = slack_to_discord::post_channel(
+            &db,
+            &sink,
+            &discord_channel.id.to_string(),
+            &channel,
+            &users,
+            &options,
+        )
+        .await;
+        if let Some(bars) = &bars {
+            bars.finish_channel(&channel, bar);
+        }
+        save_checkpoint(&mut checkpoint, &db, &channel, &result).await?;
+        summary.record(&channel.name, result, opts.continue_on_error)?;
+        if summary.out_of_time {
+            break;
+        }
+    }
+    summary.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_variables_come_out_of_every_config_format_as_they_are() {
+        let value = r#"C:\exports "quoted" 'single'"#;
+        std::env::set_var("S2D_TEST_ARCHIVED_CATEGORY", value);
+        let configs = [
+            (
+                ConfigFormat::Json,
+                r#"{"channel": {}, "archived_category": "${S2D_TEST_ARCHIVED_CATEGORY}"}"#,
+            ),
+            (
+                ConfigFormat::Toml,
+                "archived_category = '${S2D_TEST_ARCHIVED_CATEGORY}'\n[channel]\n",
+            ),
+            (
+                ConfigFormat::Toml,
+                "archived_category = \"${S2D_TEST_ARCHIVED_CATEGORY}\"\n[channel]\n",
+            ),
+            (
+                ConfigFormat::Yaml,
+                "channel: {}\narchived_category: ${S2D_TEST_ARCHIVED_CATEGORY}\n",
+            ),
+            (
+                ConfigFormat::Yaml,
+                "channel: {}\narchived_category: '${S2D_TEST_ARCHIVED_CATEGORY}'\n",
+            ),
+        ];
+        for (format, src) in configs {
+            let config = format.parse(src).unwrap();
+            assert_eq!(config.archived_category.as_deref(), Some(value), "{}", src);
+        }
+    }
+
+    #[test]
+    fn unset_environment_variables_are_all_named() {
+        let src = r#"{"channel": {"${S2D_TEST_UNSET_KEY}": "${S2D_TEST_UNSET_B}"}, "archived_category": "${S2D_TEST_UNSET_A}"}"#;
+        let error = ConfigFormat::Json.parse(src).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "config refers to unset environment variables: S2D_TEST_UNSET_A, S2D_TEST_UNSET_B, \
+             S2D_TEST_UNSET_KEY"
+        );
     }
-    Ok(())
 }
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::discord;
+use crate::sink::{MessageSink, Posted};
+
+#[derive(Default)]
+struct Document {
+    next_id: usize,
+    posts: Vec<(String, String)>,
+    replies: HashMap<String, Vec<String>>,
+    threads: HashMap<String, String>,
+}
+
+/// Writes one channel as a single Markdown document. Thread replies are quoted under their
+/// parent and attachments are saved into a directory named after the channel.
+pub struct MarkdownSink {
+    output_dir: PathBuf,
+    name: String,
+    document: Mutex<Document>,
+}
+
+impl MarkdownSink {
+    pub fn new(output_dir: &Path, name: &str) -> Self {
+        Self {
+            output_dir: output_dir.to_owned(),
+            name: name.to_owned(),
+            document: Mutex::new(Document::default()),
+        }
+    }
+
+    pub fn write(&self) -> Result<PathBuf, anyhow::Error> {
+        let document = self.document.lock().unwrap();
+        let mut out = format!("# {}\n\n", self.name);
+        for (id, post) in &document.posts {
+            out.push_str(post);
+            out.push('\n');
+            for reply in document.replies.get(id).into_iter().flatten() {
+                for line in reply.lines() {
+                    writeln!(out, "> {}", line).unwrap();
+                }
+                writeln!(out).unwrap();
+            }
+        }
+        let path = self.output_dir.join(format!("{}.md", self.name));
+        std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?;
+        info!("exported {} to {}", self.name, path.display());
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl MessageSink for MarkdownSink {
+    async fn post(
+        &self,
+        channel: &str,
+        message: &discord::MessagePost,
+        files: Vec<(String, discord::FilePost)>,
+    ) -> Result<Posted, anyhow::Error> {
+        let index = {
+            let mut document = self.document.lock().unwrap();
+            document.next_id += 1;
+            document.next_id
+        };
+        // ids end up in `posts`, so they have to be unique across channels
+        let id = format!("{}/{}", self.name, index);
+
+        let mut post = message.content.clone();
+        if !files.is_empty() {
+            let files_dir = self.output_dir.join(&self.name);
+            tokio::fs::create_dir_all(&files_dir)
+                .await
+                .with_context(|| format!("create {}", files_dir.display()))?;
+            for (filename, file) in files {
+                let filename = format!("{}-{}", index, filename);
+                let path = files_dir.join(&filename);
+                tokio::fs::write(&path, file.body)
+                    .await
+                    .with_context(|| format!("write {}", path.display()))?;
+                writeln!(post, "- [{}](<{}/{}>)", file.title, self.name, filename).unwrap();
+            }
+        }
+
+        let mut document = self.document.lock().unwrap();
+        if let Some(parent) = document.threads.get(channel).cloned() {
+            document.replies.entry(parent).or_default().push(post);
+        } else {
+            document.posts.push((id.clone(), post));
+        }
+        // the file is written long after, it has no time to tell
+        Ok(Posted {
+            id,
+            timestamp: None,
+            thread: None,
+        })
+    }
+
+    async fn start_thread(
+        &self,
+        channel: &str,
+        message: &str,
+        _name: &str,
+    ) -> Result<String, anyhow::Error> {
+        let thread = format!("{}/{}", channel, message);
+        self.document
+            .lock()
+            .unwrap()
+            .threads
+            .insert(thread.clone(), message.to_owned());
+        Ok(thread)
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use tokio::time::sleep;
+use tracing::info;
+
+use crate::discord;
+
+/// Destination of migrated messages.
+///
+/// `channel` is whatever id the sink hands out for a channel or thread. [`post_channel`]
+/// records these ids in `posts` so an interrupted run can resume.
+///
+/// [`post_channel`]: crate::post_channel
+#[async_trait]
+pub trait MessageSink {
+    /// Largest attachment the destination accepts, in bytes.
+    fn attachment_limit(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Post a message.
+    async fn post(
+        &self,
+        channel: &str,
+        message: &discord::MessagePost,
+        files: Vec<(String, discord::FilePost)>,
+    ) -> Result<Posted, anyhow::Error>;
+
+    /// Start a thread on `message` and return the channel id to post replies to.
+    async fn start_thread(
+        &self,
+        channel: &str,
+        message: &str,
+        name: &str,
+    ) -> Result<String, anyhow::Error>;
+
+    /// Start a post of the forum `channel` with `message` as its first message. The post's
+    /// thread is in [`Posted::thread`]. Sinks without forums post `message` and start a thread
+    /// on it.
+    async fn post_forum_thread(
+        &self,
+        channel: &str,
+        post: &ForumPost,
+        message: &discord::MessagePost,
+        files: Vec<(String, discord::FilePost)>,
+    ) -> Result<Posted, anyhow::Error> {
+        let posted = self.post(channel, message, files).await?;
+        let thread = self.start_thread(channel, &posted.id, &post.name).await?;
+        Ok(Posted {
+            thread: Some(thread),
+            ..posted
+        })
+    }
+
+    /// Pin a posted message.
+    async fn pin(&self, _channel: &str, _message: &str) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Delete a posted message.
+    async fn delete(&self, _channel: &str, _message: &str) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Delete a thread along with every message in it.
+    async fn delete_thread(&self, _thread: &str) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Called after a reply is posted, with the reply count Slack recorded for the thread.
+    async fn finish_thread(&self, _thread: &str, _reply_count: u64) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// A message a sink posted.
+#[derive(Debug, Clone)]
+pub struct Posted {
+    pub id: String,
+    /// When the destination says the message was posted, if it tells.
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    /// Thread of the forum post the message started.
+    pub thread: Option<String>,
+}
+
+/// Name and tag ids of a forum post.
+#[derive(Debug, Clone)]
+pub struct ForumPost {
+    pub name: String,
+    pub tags: Vec<String>,
+}
+
+pub struct DiscordSink<'a> {
+    client: &'a discord::DiscordClient,
+}
+
+impl<'a> DiscordSink<'a> {
+    pub fn new(client: &'a discord::DiscordClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl<'a> MessageSink for DiscordSink<'a> {
+    fn attachment_limit(&self) -> usize {
+        8 * 1024 * 1024
+    }
+
+    async fn post(
+        &self,
+        channel: &str,
+        message: &discord::MessagePost,
+        files: Vec<(String, discord::FilePost)>,
+    ) -> Result<Posted, anyhow::Error> {
+        let msg = self
+            .client
+            .post_message(&channel.to_owned().into(), message, files)
+            .await?;
+        sleep(Duration::from_millis(1000)).await;
+        Ok(Posted {
+            id: msg.id.to_string(),
+            timestamp: msg
+                .timestamp
+                .as_deref()
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok()),
+            thread: None,
+        })
+    }
+
+    async fn post_forum_thread(
+        &self,
+        channel: &str,
+        post: &ForumPost,
+        message: &discord::MessagePost,
+        files: Vec<(String, discord::FilePost)>,
+    ) -> Result<Posted, anyhow::Error> {
+        let thread = self
+            .client
+            .post_forum_thread(
+                &channel.to_owned().into(),
+                &post.name,
+                &post.tags,
+                message,
+                files,
+            )
+            .await?;
+        sleep(Duration::from_millis(1000)).await;
+        // the first message of a forum post shares the id of its thread
+        let (id, timestamp) = match thread.message {
+            Some(message) => (message.id.to_string(), message.timestamp),
+            None => (thread.id.to_string(), None),
+        };
+        Ok(Posted {
+            id,
+            timestamp: timestamp
+                .as_deref()
+                .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok()),
+            thread: Some(thread.id.to_string()),
+        })
+    }
+
+    async fn start_thread(
+        &self,
+        channel: &str,
+        message: &str,
+        name: &str,
+    ) -> Result<String, anyhow::Error> {
+        let thread = match self
+            .client
+            .start_thread(&channel.to_owned().into(), &message.to_owned().into(), name)
+            .await
+        {
+            Ok(thread) => thread,
+            // a thread started from a message shares its id, so one left by an interrupted
+            // run can be looked up instead of failing
+            Err(e) => match self.client.get_channel(&message.to_owned().into()).await {
+                Ok(thread) if thread.channel_type == discord::ChannelType::PublicThread => {
+                    info!("reuse thread {}", thread.id);
+                    thread
+                }
+                _ => return Err(e.into()),
+            },
+        };
+        Ok(thread.id.to_string())
+    }
+
+    async fn pin(&self, channel: &str, message: &str) -> Result<(), anyhow::Error> {
+        self.client
+            .pin_message(&channel.to_owned().into(), &message.to_owned().into())
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, channel: &str, message: &str) -> Result<(), anyhow::Error> {
+        self.client
+            .delete_message(&channel.to_owned().into(), &message.to_owned().into())
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_thread(&self, thread: &str) -> Result<(), anyhow::Error> {
+        self.client
+            .delete_channel(&thread.to_owned().into())
+            .await?;
+        Ok(())
+    }
+
+    async fn finish_thread(&self, thread: &str, reply_count: u64) -> Result<(), anyhow::Error> {
+        let thread_id = thread.to_owned().into();
+        let thread = self.client.get_channel(&thread_id).await?;
+        if thread.message_count == Some(reply_count) {
+            info!("thread {} is over", thread_id);
+            self.client.archive_channel(&thread_id).await?;
+        }
+        Ok(())
+    }
+}
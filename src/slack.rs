@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use chrono::{DateTime, NaiveDateTime, Utc};
 use chrono_tz::Tz;
-use serde::{de::Visitor, Deserialize, Deserializer};
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use sqlx::{Database, Decode, Encode};
 pub type MessagePerDay = Vec<Message>;
 
@@ -10,10 +10,63 @@ pub fn hello() -> String {
     "Hello World!".into()
 }
 
+/// A Slack `ts`. The counter tells apart messages of one channel that share a `ts`, as
+/// happens in merged exports, and is 0 for the first of them.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
-pub struct TimeStamp(DateTime<Utc>);
+pub struct TimeStamp(DateTime<Utc>, u32);
 
 impl TimeStamp {
+    /// `None` when out of chrono's range.
+    pub fn from_unix(secs: i64, nsecs: u32) -> Option<Self> {
+        NaiveDateTime::from_timestamp_opt(secs, nsecs)
+            .map(|native| TimeStamp(DateTime::from_utc(native, Utc), 0))
+    }
+
+    /// This ts for the `collision`th other message sharing it.
+    pub fn with_collision(&self, collision: u32) -> Self {
+        TimeStamp(self.0, collision)
+    }
+
+    /// `#<counter>` for messages sharing a ts, empty for the first.
+    fn collision_suffix(&self) -> String {
+        match self.1 {
+            0 => String::new(),
+            collision => format!("#{}", collision),
+        }
+    }
+
+    /// Parse a Slack `ts` like `1641000000.000100`. The digits after the dot are kept as is in
+    /// the nanoseconds, as Slack compares them. A `#<counter>` suffix, as stored for messages
+    /// sharing a ts, is read back too.
+    pub fn parse(src: &str) -> anyhow::Result<Self> {
+        let (src, collision) = match src.split_once('#') {
+            Some((src, collision)) => (
+                src,
+                collision
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("parse collision counter due to {}", e))?,
+            ),
+            None => (src, 0),
+        };
+        let mut splited = src.split('.');
+
+        let secs: i64 = splited
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing secs"))?
+            .parse()
+            .map_err(|e| anyhow::anyhow!("parse secs due to {}", e))?;
+
+        let nsecs: u32 = splited
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing nsecs"))?
+            .parse()
+            .map_err(|e| anyhow::anyhow!("parse nsecs due to {}", e))?;
+
+        Self::from_unix(secs, nsecs)
+            .map(|ts| ts.with_collision(collision))
+            .ok_or_else(|| anyhow::anyhow!("{} is out of range", src))
+    }
+
     pub fn date(&self) -> &DateTime<Utc> {
         &self.0
     }
@@ -21,11 +74,23 @@ impl TimeStamp {
     pub fn jtc_date(&self) -> DateTime<Tz> {
         self.0.with_timezone(&chrono_tz::Asia::Tokyo)
     }
+
+    /// Link to the message in the workspace at `workspace_url`, like `https://example.slack.com`.
+    pub fn permalink(&self, workspace_url: &str, channel_id: &str) -> String {
+        // parse keeps the six fraction digits as is in the nanoseconds
+        format!(
+            "{}/archives/{}/p{}{:06}",
+            workspace_url.trim_end_matches('/'),
+            channel_id,
+            self.0.timestamp(),
+            self.0.timestamp_subsec_nanos()
+        )
+    }
 }
 
 impl Display for TimeStamp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{}", self.0))
+        f.write_fmt(format_args!("{}{}", self.0, self.collision_suffix()))
     }
 }
 
@@ -37,7 +102,7 @@ where
         value: <DB as sqlx::database::HasValueRef<'r>>::ValueRef,
     ) -> Result<Self, sqlx::error::BoxDynError> {
         let s = <&str as sqlx::Decode<'r, DB>>::decode(value)?;
-        let timestamp = parse_timestamp(s)?;
+        let timestamp = TimeStamp::parse(s)?;
         Ok(timestamp)
     }
 }
@@ -52,7 +117,7 @@ where
     ) -> sqlx::encode::IsNull {
         let secs = self.0.timestamp();
         let nsecs = self.0.timestamp_subsec_nanos();
-        let s = format!("{}.{}", secs, nsecs);
+        let s = format!("{}.{}{}", secs, nsecs, self.collision_suffix());
         <String as Encode<'q, DB>>::encode_by_ref(&s, buf)
     }
 }
@@ -68,26 +133,6 @@ where
 
 struct TimeStampVisitor;
 
-fn parse_timestamp(src: &str) -> anyhow::Result<TimeStamp> {
-    let mut splited = src.split('.');
-
-    let secs: i64 = splited
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("missing secs"))?
-        .parse()
-        .map_err(|e| anyhow::anyhow!("parse secs due to {}", e))?;
-
-    let nsecs: u32 = splited
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("missing nsecs"))?
-        .parse()
-        .map_err(|e| anyhow::anyhow!("parse nsecs due to {}", e))?;
-
-    let native = NaiveDateTime::from_timestamp(secs, nsecs);
-    let utc = DateTime::from_utc(native, Utc);
-    Ok(TimeStamp(utc))
-}
-
 impl<'de> Visitor<'de> for TimeStampVisitor {
     type Value = TimeStamp;
 
@@ -99,10 +144,50 @@ impl<'de> Visitor<'de> for TimeStampVisitor {
     where
         E: serde::de::Error,
     {
-        parse_timestamp(v).map_err(|e| E::custom(e))
+        TimeStamp::parse(v).map_err(|e| E::custom(e))
     }
 }
 
+/// Written back as Slack writes it, `<secs>.<6 digits>`, followed by the counter of a
+/// message sharing its ts.
+impl Serialize for TimeStamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "{}.{:06}{}",
+            self.0.timestamp(),
+            self.0.timestamp_subsec_nanos(),
+            self.collision_suffix()
+        ))
+    }
+}
+
+/// Download URLs of exports carry the workspace token in `t`. This is `url` without it, for
+/// links that end up somewhere else.
+pub fn strip_token(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(mut parsed) => {
+            let query = parsed
+                .query_pairs()
+                .filter(|(key, _)| key != "t")
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                .collect::<Vec<_>>();
+            parsed.set_query(None);
+            if !query.is_empty() {
+                parsed.query_pairs_mut().extend_pairs(query);
+            }
+            parsed.into()
+        }
+        Err(_) => url.to_owned(),
+    }
+}
+
+fn serialize_without_token<S: Serializer>(
+    url: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    url.as_deref().map(strip_token).serialize(serializer)
+}
+
 impl<'de> Deserialize<'de> for TimeStamp {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -112,7 +197,7 @@ impl<'de> Deserialize<'de> for TimeStamp {
     }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum MessageSubType {
     #[serde(rename = "channel_join")]
     Join,
@@ -134,29 +219,114 @@ pub enum MessageSubType {
     Unarchive,
 }
 
-#[derive(Deserialize, Debug)]
+impl MessageSubType {
+    /// Whether Slack posted the message itself about the channel, like joins and topic changes.
+    pub fn is_system(&self) -> bool {
+        !matches!(self, Self::ThreadBroadcast | Self::Tombstone)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Message {
     #[serde(rename = "message")]
     Message {
         text: String,
         files: Option<Vec<File>>,
+        /// Empty for some file shares and bot posts, see [`Message::author`].
+        #[serde(default)]
         user: String,
+        /// Display name of a bot or integration.
+        username: Option<String>,
+        bot_id: Option<String>,
         subtype: Option<MessageSubType>,
         ts: TimeStamp,
         reply_count: Option<u64>,
         thread_ts: Option<TimeStamp>,
+        attachments: Option<Vec<Attachment>>,
+        #[serde(default)]
+        is_starred: bool,
+        /// Ids of the channels the message is pinned in.
+        #[serde(default)]
+        pinned_to: Vec<String>,
+        #[serde(default)]
+        reactions: Vec<Reaction>,
+        /// Block kit layout, kept as JSON, see [`crate::blocks::render_blocks`].
+        #[serde(default)]
+        blocks: Vec<serde_json::Value>,
     },
 }
 
-#[derive(Deserialize, Debug)]
+impl Message {
+    pub fn ts(&self) -> &TimeStamp {
+        match self {
+            Message::Message { ts, .. } => ts,
+        }
+    }
+
+    /// User id of the poster, falling back to the bot's `username`, then its `bot_id`, then
+    /// `unknown` for posts that carry none.
+    pub fn author(&self) -> &str {
+        match self {
+            Message::Message {
+                user,
+                username,
+                bot_id,
+                ..
+            } => Some(user.as_str())
+                .filter(|user| !user.is_empty())
+                .or(username.as_deref())
+                .or(bot_id.as_deref())
+                .unwrap_or("unknown"),
+        }
+    }
+}
+
+/// Emoji reactions of one kind on a message.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reaction {
+    /// Shortcode without colons, like `+1` or `+1::skin-tone-2`, or a custom emoji name.
+    pub name: String,
+    pub count: u64,
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+/// Legacy message attachment, used for link unfurls and app message cards.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Attachment {
+    pub pretext: Option<String>,
+    pub author_name: Option<String>,
+    pub title: Option<String>,
+    pub title_link: Option<String>,
+    pub text: Option<String>,
+    pub fields: Option<Vec<AttachmentField>>,
+    pub fallback: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AttachmentField {
+    pub title: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "mode")]
 pub enum File {
     #[serde(rename = "hosted")]
+    /// Some files come with no `url_private_download`, only with permalinks to the file's page
+    /// in Slack, which are linked instead of attaching the file.
     Hosted {
         name: String,
         title: String,
-        url_private_download: String,
+        #[serde(default, serialize_with = "serialize_without_token")]
+        url_private_download: Option<String>,
+        permalink: Option<String>,
+        permalink_public: Option<String>,
+        /// Bytes, as Slack reports them.
+        size: Option<u64>,
+        /// Slack's file type, like `png` or `pdf`.
+        filetype: Option<String>,
     },
     #[serde(rename = "tombstone")]
     Tombstone,
@@ -170,6 +340,18 @@ pub enum File {
 pub struct Channel {
     pub name: String,
     pub id: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub is_archived: bool,
+}
+
+/// Entry of `mpims.json` or `dms.json`. Only mpims carry a `name`.
+#[derive(Deserialize)]
+pub struct DirectMessage {
+    pub id: String,
+    pub name: Option<String>,
+    pub members: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
@@ -179,6 +361,19 @@ pub struct User {
     pub name: String,
 }
 
+/// How a user is named in migrated messages.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NameStyle {
+    /// `Real Name`, the username when there is none.
+    #[default]
+    RealName,
+    /// `@username`.
+    Username,
+    /// `Real Name (@username)`.
+    Both,
+}
+
 impl User {
     pub fn readable_name(&self) -> &str {
         if let Some(real_name) = &self.real_name {
@@ -187,4 +382,85 @@ impl User {
             &self.name
         }
     }
+
+    pub fn display_name(&self, style: NameStyle) -> String {
+        match (style, &self.real_name) {
+            (NameStyle::RealName, _) => self.readable_name().to_owned(),
+            (NameStyle::Username, _) | (NameStyle::Both, None) => format!("@{}", self.name),
+            (NameStyle::Both, Some(real_name)) => format!("{} (@{})", real_name, self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(real_name: Option<&str>) -> User {
+        User {
+            id: "U1".to_owned(),
+            real_name: real_name.map(str::to_owned),
+            name: "alice".to_owned(),
+        }
+    }
+
+    #[test]
+    fn users_are_named_in_each_style() {
+        let alice = user(Some("Alice Liddell"));
+        assert_eq!(alice.display_name(NameStyle::RealName), "Alice Liddell");
+        assert_eq!(alice.display_name(NameStyle::Username), "@alice");
+        assert_eq!(
+            alice.display_name(NameStyle::Both),
+            "Alice Liddell (@alice)"
+        );
+    }
+
+    #[test]
+    fn users_without_a_real_name_go_by_their_username() {
+        let alice = user(None);
+        assert_eq!(alice.display_name(NameStyle::RealName), "alice");
+        assert_eq!(alice.display_name(NameStyle::Username), "@alice");
+        assert_eq!(alice.display_name(NameStyle::Both), "@alice");
+    }
+
+    #[test]
+    fn name_styles_are_configured_in_lowercase() {
+        for (config, style) in [
+            (r#""realname""#, NameStyle::RealName),
+            (r#""username""#, NameStyle::Username),
+            (r#""both""#, NameStyle::Both),
+        ] {
+            assert_eq!(serde_json::from_str::<NameStyle>(config).unwrap(), style);
+        }
+    }
+
+    fn message(json: &str) -> Message {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn file_shares_without_a_user_are_by_unknown() {
+        let share = message(
+            r#"{"type": "message", "subtype": null, "upload": true, "text": "", "ts": "1641000000.000100",
+                "files": [{"id": "F1", "mode": "tombstone"}]}"#,
+        );
+        assert_eq!(share.author(), "unknown");
+    }
+
+    #[test]
+    fn bot_posts_are_by_their_username_then_bot_id() {
+        let named = message(
+            r#"{"type": "message", "text": "deployed", "ts": "1641000000.000100",
+                "username": "deploy-bot", "bot_id": "B1"}"#,
+        );
+        assert_eq!(named.author(), "deploy-bot");
+        let unnamed = message(
+            r#"{"type": "message", "text": "deployed", "ts": "1641000000.000100", "user": "", "bot_id": "B1"}"#,
+        );
+        assert_eq!(unnamed.author(), "B1");
+        let user = message(
+            r#"{"type": "message", "text": "hi", "ts": "1641000000.000100", "user": "U1", "bot_id": "B1"}"#,
+        );
+        assert_eq!(user.author(), "U1");
+    }
 }
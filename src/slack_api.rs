@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+use crate::discord::SecretLoadError;
+use crate::slack;
+
+const SLACK_ENDPOINT_COMMON: &str = "https://slack.com/api";
+
+/// Items requested per page, the most Slack recommends.
+const PAGE_LIMIT: &str = "200";
+
+pub struct SlackToken(String);
+
+impl SlackToken {
+    pub fn from_env(env_name: &str) -> Result<Self, SecretLoadError> {
+        match std::env::var(env_name) {
+            Ok(var) => Ok(Self(var)),
+            Err(std::env::VarError::NotPresent) => Err(SecretLoadError::NotPresentByEnvVar),
+            Err(std::env::VarError::NotUnicode(_)) => Err(SecretLoadError::NotUnicode),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request :: {0}")]
+    Request(reqwest::Error),
+    #[error("schema :: {0}")]
+    Schema(serde_json::Error),
+    #[error("{method} :: {error}")]
+    Api { method: String, error: String },
+}
+
+/// Entry of `conversations.list`. DMs carry no `name` and public channels no `is_im`/`is_mpim`.
+#[derive(Deserialize, Debug)]
+pub struct Conversation {
+    pub id: String,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub is_im: bool,
+    #[serde(default)]
+    pub is_mpim: bool,
+    #[serde(default)]
+    pub is_private: bool,
+    #[serde(default)]
+    pub is_archived: bool,
+}
+
+/// Reads a live workspace through the Slack Web API.
+pub struct SlackApiClient {
+    token: SlackToken,
+    http: Client,
+}
+
+impl SlackApiClient {
+    pub fn new(token: SlackToken) -> Self {
+        Self {
+            token,
+            http: Client::new(),
+        }
+    }
+
+    /// Call `method` page by page and collect the `field` array of every page. 429 responses
+    /// are retried after the `Retry-After` Slack sends.
+    async fn paginate(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+        field: &str,
+    ) -> Result<Vec<Value>, Error> {
+        let url = format!("{}/{}", SLACK_ENDPOINT_COMMON, method);
+        let mut items = Vec::new();
+        let mut cursor = String::new();
+        loop {
+            let mut query = params.to_vec();
+            query.push(("limit", PAGE_LIMIT));
+            if !cursor.is_empty() {
+                query.push(("cursor", &cursor));
+            }
+            let response = self
+                .http
+                .get(&url)
+                .bearer_auth(self.token.as_str())
+                .query(&query)
+                .send()
+                .await
+                .map_err(Error::Request)?;
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(1);
+                warn!("{} rate limited, retry in {}s", method, retry_after);
+                sleep(Duration::from_secs(retry_after)).await;
+                continue;
+            }
+            let mut page: Value = response.json().await.map_err(Error::Request)?;
+            if page["ok"] != Value::Bool(true) {
+                return Err(Error::Api {
+                    method: method.to_owned(),
+                    error: page["error"].as_str().unwrap_or("unknown").to_owned(),
+                });
+            }
+            if let Value::Array(page_items) = page[field].take() {
+                items.extend(page_items);
+            }
+            cursor = page["response_metadata"]["next_cursor"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned();
+            if cursor.is_empty() {
+                debug!("{} returned {} {}", method, items.len(), field);
+                return Ok(items);
+            }
+        }
+    }
+
+    pub async fn users(&self) -> Result<Vec<slack::User>, Error> {
+        self.paginate("users.list", &[], "members")
+            .await?
+            .into_iter()
+            .map(|user| serde_json::from_value(user).map_err(Error::Schema))
+            .collect()
+    }
+
+    pub async fn conversations(&self, types: &str) -> Result<Vec<Conversation>, Error> {
+        self.paginate("conversations.list", &[("types", types)], "channels")
+            .await?
+            .into_iter()
+            .map(|conversation| serde_json::from_value(conversation).map_err(Error::Schema))
+            .collect()
+    }
+
+    pub async fn members(&self, channel: &str) -> Result<Vec<String>, Error> {
+        self.paginate("conversations.members", &[("channel", channel)], "members")
+            .await?
+            .into_iter()
+            .map(|member| serde_json::from_value(member).map_err(Error::Schema))
+            .collect()
+    }
+
+    /// Every message of `channel`, replies included, in no particular order.
+    pub async fn history(&self, channel: &str) -> Result<Vec<slack::Message>, Error> {
+        let mut messages = self
+            .paginate("conversations.history", &[("channel", channel)], "messages")
+            .await?;
+        let threads = messages
+            .iter()
+            .filter(|message| message["reply_count"].as_u64().unwrap_or(0) > 0)
+            .filter_map(|message| message["ts"].as_str().map(str::to_owned))
+            .collect::<Vec<_>>();
+        for thread in threads {
+            let replies = self
+                .paginate(
+                    "conversations.replies",
+                    &[("channel", channel), ("ts", &thread)],
+                    "messages",
+                )
+                .await?;
+            // the parent comes back along with its replies
+            messages.extend(
+                replies
+                    .into_iter()
+                    .filter(|reply| reply["ts"].as_str() != Some(&thread)),
+            );
+        }
+        Ok(parse_messages(channel, messages))
+    }
+}
+
+/// Messages the `slack::Message` model does not cover, such as ones without text, are skipped.
+fn parse_messages(channel: &str, messages: Vec<Value>) -> Vec<slack::Message> {
+    messages
+        .into_iter()
+        .filter_map(|message| match serde_json::from_value(message) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!("skip unsupported message in {}: {}", channel, e);
+                None
+            }
+        })
+        .collect()
+}
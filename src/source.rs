@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tracing::{info, warn};
+
+use crate::archive::ExportArchive;
+use crate::slack_api::SlackApiClient;
+use crate::{slack, ChannelKind, LoadOptions, SlackChannel};
+
+/// Where Slack users and channels are read from.
+#[async_trait]
+pub trait SlackSource {
+    /// Every user as listed, see [`users_by_id`].
+    async fn users(&mut self) -> Result<Vec<slack::User>, anyhow::Error>;
+
+    /// Every channel `options` asks for, in the order the source lists them, with its
+    /// messages sorted by ts.
+    async fn channels(
+        &mut self,
+        users: &HashMap<String, slack::User>,
+        options: &LoadOptions,
+    ) -> Result<Vec<SlackChannel>, anyhow::Error>;
+}
+
+/// Index `users` by id, warning on ids listed twice, of which the last wins, and on users
+/// with no name to show. With `strict` these fail instead.
+pub fn users_by_id(
+    users: Vec<slack::User>,
+    strict: bool,
+) -> Result<HashMap<String, slack::User>, anyhow::Error> {
+    let mut problems = Vec::new();
+    let mut by_id = HashMap::new();
+    for user in users {
+        if user.name.is_empty() && user.real_name.as_deref().unwrap_or_default().is_empty() {
+            problems.push(format!("user {} has neither real_name nor name", user.id));
+        }
+        if let Some(previous) = by_id.insert(user.id.clone(), user) {
+            problems.push(format!("user {} is listed more than once", previous.id));
+        }
+    }
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    if strict && !problems.is_empty() {
+        anyhow::bail!("invalid user list: {}", problems.join(", "));
+    }
+    Ok(by_id)
+}
+
+/// A workspace export, zipped or unpacked.
+pub struct ExportSource<A> {
+    archive: A,
+}
+
+impl<A: ExportArchive> ExportSource<A> {
+    pub fn new(archive: A) -> Self {
+        Self { archive }
+    }
+}
+
+#[async_trait]
+impl<A: ExportArchive + Send> SlackSource for ExportSource<A> {
+    async fn users(&mut self) -> Result<Vec<slack::User>, anyhow::Error> {
+        let users = self
+            .archive
+            .open("users.json")?
+            .with_context(|| "read users.json")?;
+        serde_json::from_reader(users).with_context(|| "parse users.json")
+    }
+
+    async fn channels(
+        &mut self,
+        users: &HashMap<String, slack::User>,
+        options: &LoadOptions,
+    ) -> Result<Vec<SlackChannel>, anyhow::Error> {
+        let mut reported_percent = None;
+        let channels =
+            crate::get_channels_stream(&mut self.archive, users, options, &mut |progress| {
+                let percent = progress.entries_processed * 100 / progress.entries_total.max(1);
+                if reported_percent.is_none_or(|reported| percent >= reported + 10) {
+                    info!(
+                        "loading archive {}% ({}/{} entries, {} channels)",
+                        percent,
+                        progress.entries_processed,
+                        progress.entries_total,
+                        progress.channels_populated
+                    );
+                    reported_percent = Some(percent);
+                }
+            })?;
+        Ok(channels)
+    }
+}
+
+/// A live workspace read through the Web API. Rerunning against it syncs what was posted
+/// since, as already migrated messages are skipped through `posts`.
+pub struct ApiSource {
+    client: SlackApiClient,
+}
+
+impl ApiSource {
+    pub fn new(client: SlackApiClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl SlackSource for ApiSource {
+    async fn users(&mut self) -> Result<Vec<slack::User>, anyhow::Error> {
+        Ok(self.client.users().await.with_context(|| "list users")?)
+    }
+
+    async fn channels(
+        &mut self,
+        users: &HashMap<String, slack::User>,
+        options: &LoadOptions,
+    ) -> Result<Vec<SlackChannel>, anyhow::Error> {
+        let mut types = vec!["public_channel"];
+        if options.include_private {
+            types.push("private_channel");
+        }
+        if options.include_dms {
+            types.extend(["mpim", "im"]);
+        }
+        // archived channels are listed too, so they can be migrated like in an export
+        let conversations = self
+            .client
+            .conversations(&types.join(","))
+            .await
+            .with_context(|| "list conversations")?;
+        let mut channels = Vec::new();
+        for conversation in conversations {
+            let messages = self
+                .client
+                .history(&conversation.id)
+                .await
+                .with_context(|| format!("history of {}", conversation.id))?;
+            let members = self
+                .client
+                .members(&conversation.id)
+                .await
+                .with_context(|| format!("members of {}", conversation.id))?;
+            let channel = if conversation.is_im || conversation.is_mpim {
+                SlackChannel {
+                    id: conversation.id,
+                    name: crate::dm_channel_name(&members, users),
+                    kind: ChannelKind::DirectMessage,
+                    members,
+                    archived: conversation.is_archived,
+                    messages,
+                }
+            } else {
+                SlackChannel {
+                    name: conversation
+                        .name
+                        .with_context(|| format!("{} has no name", conversation.id))?,
+                    id: conversation.id,
+                    kind: if conversation.is_private {
+                        ChannelKind::Private
+                    } else {
+                        ChannelKind::Public
+                    },
+                    members,
+                    archived: conversation.is_archived,
+                    messages,
+                }
+            };
+            info!(
+                "fetched {} messages of {}",
+                channel.messages.len(),
+                channel.name
+            );
+            channels.push(crate::sort_messages(channel));
+        }
+        Ok(channels)
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use crate::slack;
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    User,
+    RealName,
+    Date,
+    Time,
+    Text,
+    Permalink,
+    Files,
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "user" => Some(Self::User),
+            "real_name" => Some(Self::RealName),
+            "date" => Some(Self::Date),
+            "time" => Some(Self::Time),
+            "text" => Some(Self::Text),
+            "permalink" => Some(Self::Permalink),
+            "files" => Some(Self::Files),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Var(Var),
+}
+
+/// Values a template can refer to, for one message.
+pub struct TemplateContext<'a> {
+    pub users: &'a HashMap<String, slack::User>,
+    pub user: &'a str,
+    pub ts: &'a slack::TimeStamp,
+    /// Text with mentions already resolved.
+    pub text: &'a str,
+    pub channel_id: &'a str,
+    pub workspace_url: Option<&'a str>,
+    pub files: &'a [&'a slack::File],
+}
+
+/// Per-message format with `{{user}}`, `{{real_name}}`, `{{date}}`, `{{time}}`, `{{text}}`,
+/// `{{permalink}}` and `{{files}}` placeholders. Dates and times are in JST like the built-in
+/// header.
+#[derive(Debug, Clone)]
+pub struct MessageTemplate {
+    segments: Vec<Segment>,
+}
+
+impl MessageTemplate {
+    pub fn parse(src: &str) -> Result<Self, anyhow::Error> {
+        let mut segments = Vec::new();
+        let mut rest = src;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| anyhow::anyhow!("unclosed {{{{ in template"))?
+                + start;
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let name = rest[start + 2..end].trim();
+            let var = Var::parse(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown template variable {}", name))?;
+            segments.push(Segment::Var(var));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let user = context.users.get(context.user);
+        let date = context.ts.jtc_date();
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Var(Var::User) => {
+                    out.push_str(user.map(|user| user.name.as_str()).unwrap_or(context.user))
+                }
+                Segment::Var(Var::RealName) => out.push_str(
+                    user.map(|user| user.readable_name())
+                        .unwrap_or(context.user),
+                ),
+                Segment::Var(Var::Date) => out.push_str(&date.format("%Y-%m-%d").to_string()),
+                Segment::Var(Var::Time) => out.push_str(&date.format("%H:%M:%S").to_string()),
+                Segment::Var(Var::Text) => out.push_str(context.text),
+                // permalinks need the workspace, which the export does not record
+                Segment::Var(Var::Permalink) => {
+                    if let Some(workspace_url) = context.workspace_url {
+                        out.push_str(&context.ts.permalink(workspace_url, context.channel_id))
+                    }
+                }
+                Segment::Var(Var::Files) => out.push_str(
+                    &context
+                        .files
+                        .iter()
+                        .filter_map(|file| match file {
+                            slack::File::Hosted { name, .. } => Some(name.as_str()),
+                            slack::File::External { name, .. } => Some(name.as_str()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            }
+        }
+        out
+    }
+}